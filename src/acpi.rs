@@ -0,0 +1,118 @@
+use core::{mem, ptr};
+
+use x86_64::{PhysAddr, VirtAddr};
+
+use crate::memory;
+
+// just enough ACPI to find a table by its signature, the tables are read in place through the
+// physical memory mapping
+
+const RSDP_SIGNATURE: &[u8; 8] = b"RSD PTR ";
+// the BIOS puts the RSDP in the first KiB of the EBDA or in the read-only BIOS area
+const EBDA_SEGMENT_POINTER: u64 = 0x40e;
+const BIOS_AREA: (u64, u64) = (0xe_0000, 0x10_0000);
+const SDT_HEADER_SIZE: u64 = 36;
+
+// the header every system description table starts with
+#[derive(Debug, Clone, Copy)]
+pub struct SdtHeader {
+    pub addr: PhysAddr,
+    pub signature: [u8; 4],
+    pub length: u32,
+    pub revision: u8,
+}
+
+// the table with `signature`, checksum verified, `None` if the firmware doesn't provide it
+pub fn find_table(signature: &[u8; 4]) -> Option<SdtHeader> {
+    let (root, entry_size) = root_table()?;
+    let entries = (u64::from(root.length) - SDT_HEADER_SIZE) / entry_size;
+
+    (0..entries)
+        .filter_map(|index| {
+            let entry = root.addr + SDT_HEADER_SIZE + index * entry_size;
+            let addr = match entry_size {
+                8 => read_phys::<u64>(entry)?,
+                _ => u64::from(read_phys::<u32>(entry)?),
+            };
+            table_at(PhysAddr::new(addr))
+        })
+        .find(|table| &table.signature == signature)
+}
+
+// reads the `T` at `offset` bytes into `table`
+pub fn read_field<T: Copy>(table: &SdtHeader, offset: u64) -> Option<T> {
+    if offset + mem::size_of::<T>() as u64 > u64::from(table.length) {
+        return None;
+    }
+    read_phys(table.addr + offset)
+}
+
+// the RSDT or, from ACPI 2.0 on, the XSDT, with the size of their entries
+fn root_table() -> Option<(SdtHeader, u64)> {
+    let rsdp = find_rsdp()?;
+    let revision = read_phys::<u8>(rsdp + 15u64)?;
+
+    if revision >= 2 {
+        let length = read_phys::<u32>(rsdp + 20u64)?;
+        if checksum(rsdp, u64::from(length)) == Some(0) {
+            let xsdt = read_phys::<u64>(rsdp + 24u64)?;
+            if let Some(table) = table_at(PhysAddr::new(xsdt)) {
+                return Some((table, 8));
+            }
+        }
+    }
+
+    let rsdt = read_phys::<u32>(rsdp + 16u64)?;
+    Some((table_at(PhysAddr::new(u64::from(rsdt)))?, 4))
+}
+
+fn find_rsdp() -> Option<PhysAddr> {
+    let ebda = u64::from(read_phys::<u16>(PhysAddr::new(EBDA_SEGMENT_POINTER))?) << 4;
+    let areas = [(ebda, ebda + 1024), BIOS_AREA];
+
+    areas
+        .into_iter()
+        .filter(|&(start, _)| start != 0)
+        .flat_map(|(start, end)| (start..end).step_by(16))
+        .map(PhysAddr::new)
+        .find(|&addr| {
+            read_phys::<[u8; 8]>(addr).as_ref() == Some(RSDP_SIGNATURE)
+                && checksum(addr, 20) == Some(0)
+        })
+}
+
+fn table_at(addr: PhysAddr) -> Option<SdtHeader> {
+    let table = SdtHeader {
+        addr,
+        signature: read_phys(addr)?,
+        length: read_phys(addr + 4u64)?,
+        revision: read_phys(addr + 8u64)?,
+    };
+
+    let valid = u64::from(table.length) >= SDT_HEADER_SIZE
+        && checksum(addr, table.length.into()) == Some(0);
+    valid.then_some(table)
+}
+
+// the sum of `len` bytes at `addr`, ACPI structures sum up to 0
+fn checksum(addr: PhysAddr, len: u64) -> Option<u8> {
+    (0..len).try_fold(0u8, |sum, offset| {
+        Some(sum.wrapping_add(read_phys::<u8>(addr + offset)?))
+    })
+}
+
+// `None` if memory isn't set up yet or `addr` lies outside of the physical memory mapping
+fn read_phys<T: Copy>(addr: PhysAddr) -> Option<T> {
+    let virt = phys_to_virt(addr)?;
+    let last = phys_to_virt(addr + (mem::size_of::<T>() as u64 - 1))?;
+    let mapped = |virt: VirtAddr| memory::translate(virt).is_some_and(|t| t.is_mapped());
+    if !mapped(virt) || !mapped(last) {
+        return None;
+    }
+
+    Some(unsafe { ptr::read_unaligned(virt.as_ptr::<T>()) })
+}
+
+fn phys_to_virt(addr: PhysAddr) -> Option<VirtAddr> {
+    Some(memory::physical_memory_offset()? + addr.as_u64())
+}
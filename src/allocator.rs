@@ -1,27 +1,164 @@
 use core::{
     alloc::{GlobalAlloc, Layout},
-    ptr::null_mut,
+    ptr::{self, null_mut},
 };
 
 use x86_64::{
     VirtAddr,
-    structures::paging::{
-        FrameAllocator, Mapper, Page, PageTableFlags, Size4KiB, mapper::MapToError,
-    },
+    structures::paging::{PageTableFlags, Size4KiB, mapper::MapToError},
 };
 
-use crate::allocator::fixed_size_block::FixedSizeBlockAllocator;
+use crate::memory;
+use crate::memory::vma::{self, VmaBacking};
+use crate::serial_println;
+use crate::task::task_struct::TaskId;
 
 pub mod bump;
+pub mod debug;
 pub mod fixed_size_block;
 pub mod linked_list;
+pub mod slab;
 
 pub const HEAP_START: usize = 0x_4444_4444_0000;
 pub const HEAP_SIZE: usize = 100 * 1024; // 100 KiB
+// the heap grows on demand up to this size, only then allocations fail
+pub const HEAP_MAX_SIZE: usize = 16 * 1024 * 1024; // 16 MiB
+// the minimum number of bytes that are mapped each time the heap grows
+const HEAP_GROWTH_STEP: usize = 64 * 1024; // 64 KiB
+const PAGE_SIZE: usize = 4096;
+const HEAP_FLAGS: PageTableFlags = PageTableFlags::PRESENT
+    .union(PageTableFlags::WRITABLE)
+    .union(PageTableFlags::NO_EXECUTE);
 
+// the global allocator is chosen at build time with exactly one of the `alloc-*` features
+#[cfg(any(
+    all(feature = "alloc-bump", feature = "alloc-linked-list"),
+    all(feature = "alloc-bump", feature = "alloc-fixed-block"),
+    all(feature = "alloc-linked-list", feature = "alloc-fixed-block"),
+    not(any(
+        feature = "alloc-bump",
+        feature = "alloc-linked-list",
+        feature = "alloc-fixed-block"
+    )),
+))]
+compile_error!(
+    "exactly one of the `alloc-bump`, `alloc-linked-list` and `alloc-fixed-block` features must be enabled"
+);
+
+#[cfg(feature = "alloc-bump")]
+type GlobalHeap = bump::BumpAllocator;
+#[cfg(feature = "alloc-linked-list")]
+type GlobalHeap = linked_list::LinkedListAllocator;
+#[cfg(feature = "alloc-fixed-block")]
+type GlobalHeap = fixed_size_block::FixedSizeBlockAllocator;
+
+#[cfg_attr(not(feature = "heap-debug"), global_allocator)]
+static ALLOCATOR: Locked<GlobalHeap> = Locked::new(GlobalHeap::new());
+
+// with `heap-debug` every allocation goes through the poisoning and red zone checks first
+#[cfg(feature = "heap-debug")]
 #[global_allocator]
-// static ALLOCATOR: Locked<LinkedListAllocator> = Locked::new(LinkedListAllocator::new());
-static ALLOCATOR: Locked<FixedSizeBlockAllocator> = Locked::new(FixedSizeBlockAllocator::new());
+static DEBUG_ALLOCATOR: debug::DebugAllocator<Locked<GlobalHeap>> =
+    debug::DebugAllocator::new(&ALLOCATOR);
+
+// common interface of the heap allocators so any of them can back the global allocator
+pub trait HeapAllocator {
+    // unsafe because the caller must guarantee that the given heap range is mapped and unused
+    #[allow(clippy::missing_safety_doc)]
+    unsafe fn init(&mut self, heap_start: usize, heap_size: usize);
+
+    // unsafe because the caller must guarantee that `size` bytes after `heap_end` are mapped and unused
+    #[allow(clippy::missing_safety_doc)]
+    unsafe fn extend(&mut self, size: usize);
+
+    fn heap_end(&self) -> usize;
+
+    fn stats(&self) -> HeapStats;
+
+    // prints the allocator specific state to serial, used by the allocation error handler
+    fn print_diagnostics(&mut self);
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HeapStats {
+    pub size: usize,
+    // bytes of the live allocations, freed memory counts as free even if it isn't reusable yet
+    pub allocated: usize,
+    // the number of live allocations
+    pub allocations: usize,
+    pub high_water_mark: usize,
+}
+
+impl HeapStats {
+    pub fn free(&self) -> usize {
+        self.size - self.allocated
+    }
+}
+
+// with `heap-debug` the usage counts the sizes that were asked for, not the red zones around them
+pub fn heap_stats() -> HeapStats {
+    let stats = ALLOCATOR.lock().stats();
+    #[cfg(feature = "heap-debug")]
+    let stats = DEBUG_ALLOCATOR.stats(stats.size);
+    stats
+}
+
+// live heap bytes, their count and peak, kept by every allocator to fill in `HeapStats`
+struct HeapUsage {
+    allocated: usize,
+    allocations: usize,
+    high_water_mark: usize,
+}
+
+impl HeapUsage {
+    const fn new() -> Self {
+        HeapUsage {
+            allocated: 0,
+            allocations: 0,
+            high_water_mark: 0,
+        }
+    }
+
+    fn record_alloc(&mut self, size: usize) {
+        self.allocated += size;
+        self.allocations += 1;
+        self.high_water_mark = self.high_water_mark.max(self.allocated);
+    }
+
+    fn record_dealloc(&mut self, size: usize) {
+        self.allocated -= size;
+        self.allocations -= 1;
+    }
+
+    fn stats(&self, heap_size: usize) -> HeapStats {
+        HeapStats {
+            size: heap_size,
+            allocated: self.allocated,
+            allocations: self.allocations,
+            high_water_mark: self.high_water_mark,
+        }
+    }
+}
+
+// called when the global allocator returns null, dumps the heap state before panicking
+#[alloc_error_handler]
+fn alloc_error_handler(layout: Layout) -> ! {
+    serial_println!("ALLOCATION ERROR: {:?}", layout);
+
+    // the allocator lock is already released when the error handler runs
+    ALLOCATOR.lock().print_diagnostics();
+
+    match TaskId::current() {
+        Some(task_id) => {
+            serial_println!("  current task: {:?}", task_id);
+        }
+        None => {
+            serial_println!("  current task: none");
+        }
+    }
+
+    panic!("allocation error: {:?}", layout);
+}
 
 pub struct Locked<A> {
     inner: spin::Mutex<A>,
@@ -50,27 +187,27 @@ unsafe impl GlobalAlloc for Dummy {
     }
 }
 
-pub fn init_heap(
-    mapper: &mut impl Mapper<Size4KiB>,
-    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
-) -> Result<(), MapToError<Size4KiB>> {
-    let page_range = {
-        let heap_start = VirtAddr::new(HEAP_START as u64);
-        let heap_end = heap_start + HEAP_SIZE - 1u64;
-        let heap_start_page = Page::containing_address(heap_start);
-        let heap_end_page = Page::containing_address(heap_end);
-
-        Page::range_inclusive(heap_start_page, heap_end_page)
-    };
-
-    for page in page_range {
-        let frame = frame_allocator
-            .allocate_frame()
-            .ok_or(MapToError::FrameAllocationFailed)?;
-
-        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
-        unsafe { mapper.map_to(page, frame, flags, frame_allocator)?.flush() }
-    }
+// the heap pages are mapped with the kernel memory set up by `memory::init_kernel_memory`
+pub fn init_heap() -> Result<(), MapToError<Size4KiB>> {
+    // the whole range the heap may grow into is reserved up front
+    vma::reserve_at(
+        "kernel heap",
+        VirtAddr::new(HEAP_START as u64),
+        HEAP_MAX_SIZE as u64,
+        HEAP_FLAGS,
+        VmaBacking::External,
+    )
+    .expect("heap range is already reserved");
+
+    memory::KERNEL_MEMORY
+        .lock()
+        .as_mut()
+        .expect("kernel memory is not initialized")
+        .map_range(
+            VirtAddr::new(HEAP_START as u64),
+            HEAP_SIZE as u64,
+            HEAP_FLAGS,
+        )?;
 
     unsafe {
         ALLOCATOR.lock().init(HEAP_START, HEAP_SIZE);
@@ -79,6 +216,65 @@ pub fn init_heap(
     Ok(())
 }
 
+// retries `alloc` after growing the heap until it succeeds or the heap can't grow anymore
+fn alloc_or_grow<A: HeapAllocator>(
+    allocator: &mut A,
+    layout: Layout,
+    mut alloc: impl FnMut(&mut A) -> *mut u8,
+) -> *mut u8 {
+    loop {
+        let ptr = alloc(allocator);
+        if !ptr.is_null() {
+            return ptr;
+        }
+
+        // the padding covers aligning the allocation inside the new memory
+        let min_size = layout.size() + layout.align();
+        match grow_heap(allocator.heap_end(), min_size) {
+            Some(grown) => unsafe { allocator.extend(grown) },
+            None => return ptr::null_mut(),
+        }
+    }
+}
+
+// maps at least `min_size` more bytes right after `heap_end` and returns how many were mapped
+// called by the global allocator with its lock held, so it must not allocate itself
+fn grow_heap(heap_end: usize, min_size: usize) -> Option<usize> {
+    // allocators that manage memory outside of the kernel heap never grow
+    let heap_size = heap_end.checked_sub(HEAP_START)?;
+    let remaining = HEAP_MAX_SIZE.checked_sub(heap_size)?;
+    let grow_size = align_up(min_size.max(HEAP_GROWTH_STEP), PAGE_SIZE).min(remaining);
+    if grow_size < min_size {
+        return None;
+    }
+
+    let mut kernel_memory = memory::KERNEL_MEMORY.lock();
+    let kernel_memory = kernel_memory.as_mut()?;
+
+    // huge pages are used for the parts of the range that are aligned for them
+    let start = VirtAddr::new(heap_end as u64);
+    if kernel_memory
+        .map_range(start, grow_size as u64, HEAP_FLAGS)
+        .is_ok()
+    {
+        return Some(grow_size);
+    }
+
+    // keep the pages mapped so far even if the frames run out halfway
+    let mut mapped = 0;
+    while mapped < grow_size {
+        if kernel_memory
+            .map_range(start + mapped as u64, PAGE_SIZE as u64, HEAP_FLAGS)
+            .is_err()
+        {
+            break;
+        }
+        mapped += PAGE_SIZE;
+    }
+
+    if mapped == 0 { None } else { Some(mapped) }
+}
+
 fn align_up(addr: usize, align: usize) -> usize {
     (addr + align - 1) & !(align - 1)
 }
@@ -3,13 +3,15 @@ use core::{
     ptr,
 };
 
-use crate::allocator::{Locked, align_up};
+use crate::allocator::{HeapAllocator, HeapStats, HeapUsage, Locked, align_up, alloc_or_grow};
+use crate::serial_println;
 
 pub struct BumpAllocator {
     heap_start: usize,
     heap_end: usize,
     next: usize,
     allocations: usize,
+    usage: HeapUsage,
 }
 
 impl BumpAllocator {
@@ -19,14 +21,24 @@ impl BumpAllocator {
             heap_end: 0,
             next: 0,
             allocations: 0,
+            usage: HeapUsage::new(),
         }
     }
 
-    #[allow(clippy::missing_safety_doc)]
-    pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
-        self.heap_start = heap_start;
-        self.heap_end = self.heap_start + heap_size;
-        self.next = heap_start;
+    fn bump_alloc(&mut self, layout: Layout) -> *mut u8 {
+        let alloc_start = align_up(self.next, layout.align());
+        let alloc_end = match alloc_start.checked_add(layout.size()) {
+            Some(end) => end,
+            None => return ptr::null_mut(),
+        };
+
+        if alloc_end > self.heap_end {
+            ptr::null_mut()
+        } else {
+            self.next = alloc_end;
+            self.allocations += 1;
+            alloc_start as *mut u8
+        }
     }
 }
 
@@ -36,28 +48,51 @@ impl Default for BumpAllocator {
     }
 }
 
+impl HeapAllocator for BumpAllocator {
+    unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+        self.heap_start = heap_start;
+        self.heap_end = self.heap_start + heap_size;
+        self.next = heap_start;
+    }
+
+    unsafe fn extend(&mut self, size: usize) {
+        self.heap_end += size;
+    }
+
+    fn heap_end(&self) -> usize {
+        self.heap_end
+    }
+
+    fn stats(&self) -> HeapStats {
+        self.usage.stats(self.heap_end - self.heap_start)
+    }
+
+    fn print_diagnostics(&mut self) {
+        serial_println!(
+            "  bump heap: {} live allocations, {} B left after next {:#x}",
+            self.allocations,
+            self.heap_end - self.next,
+            self.next
+        );
+    }
+}
+
 unsafe impl GlobalAlloc for Locked<BumpAllocator> {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
         let mut bump = self.lock();
 
-        let alloc_start = align_up(bump.next, layout.align());
-        let alloc_end = match alloc_start.checked_add(layout.size()) {
-            Some(end) => end,
-            None => return ptr::null_mut(),
-        };
-
-        if alloc_end > bump.heap_end {
-            ptr::null_mut()
-        } else {
-            bump.next = alloc_end;
-            bump.allocations += 1;
-            alloc_start as *mut u8
+        let ptr = alloc_or_grow(&mut *bump, layout, |bump| bump.bump_alloc(layout));
+        if !ptr.is_null() {
+            bump.usage.record_alloc(layout.size());
         }
+
+        ptr
     }
 
-    unsafe fn dealloc(&self, _ptr: *mut u8, _layout: core::alloc::Layout) {
+    unsafe fn dealloc(&self, _ptr: *mut u8, layout: Layout) {
         let mut bump = self.lock();
 
+        bump.usage.record_dealloc(layout.size());
         bump.allocations -= 1;
         if bump.allocations == 0 {
             bump.next = bump.heap_start;
@@ -0,0 +1,173 @@
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    mem, slice,
+};
+
+use spin::Mutex;
+
+use crate::allocator::{HeapStats, HeapUsage};
+
+// new allocations are filled with this, so reads of uninitialized memory stand out
+pub const ALLOC_POISON: u8 = 0xcd;
+// freed memory is filled with this, so use-after-free reads stand out
+pub const FREE_POISON: u8 = 0xdd;
+// the red zones around every block, overwriting them is caught on dealloc
+pub const GUARD_BYTE: u8 = 0xfd;
+pub const GUARD_SIZE: usize = 16;
+
+const LIVE_MAGIC: usize = 0x_a110_ca7e_d000_0000;
+const FREED_MAGIC: usize = 0x_f4ee_d000_0000_0000;
+
+// sits right in front of the leading red zone
+// the inner allocators keep their free list node in the first two words of a freed block,
+// so the magic goes last to survive the free and reveal double frees
+#[repr(C)]
+struct BlockHeader {
+    size: usize,
+    align: usize,
+    magic: usize,
+}
+
+const HEADER_SIZE: usize = mem::size_of::<BlockHeader>();
+
+// wraps the global allocator when the `heap-debug` feature is enabled
+// every block is laid out as `[header][guard][data][guard]` inside a larger inner allocation
+pub struct DebugAllocator<A: 'static> {
+    inner: &'static A,
+    // the sizes callers asked for, the inner allocator also counts the headers and red zones
+    usage: Mutex<HeapUsage>,
+}
+
+impl<A> DebugAllocator<A> {
+    pub const fn new(inner: &'static A) -> Self {
+        DebugAllocator {
+            inner,
+            usage: Mutex::new(HeapUsage::new()),
+        }
+    }
+
+    // the usage as the callers see it, in a heap of `heap_size` bytes
+    pub fn stats(&self, heap_size: usize) -> HeapStats {
+        self.usage.lock().stats(heap_size)
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for DebugAllocator<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let (outer_layout, offset) = outer_layout(layout);
+        let block = unsafe { self.inner.alloc(outer_layout) };
+        if block.is_null() {
+            return block;
+        }
+
+        let ptr = unsafe { block.add(offset) };
+        unsafe {
+            block.write_bytes(GUARD_BYTE, offset);
+            header(ptr).write(BlockHeader {
+                size: layout.size(),
+                align: layout.align(),
+                magic: LIVE_MAGIC,
+            });
+            ptr.write_bytes(ALLOC_POISON, layout.size());
+            ptr.add(layout.size()).write_bytes(GUARD_BYTE, GUARD_SIZE);
+        }
+        self.usage.lock().record_alloc(layout.size());
+
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let header = unsafe { &mut *header(ptr) };
+
+        match header.magic {
+            LIVE_MAGIC => {}
+            FREED_MAGIC => panic!("heap-debug: double free of {:p} with {:?}", ptr, layout),
+            _ => panic!(
+                "heap-debug: free of {:p} with {:?}, which is no live allocation or has a corrupted header",
+                ptr, layout
+            ),
+        }
+
+        if header.size != layout.size() || header.align != layout.align() {
+            panic!(
+                "heap-debug: {:p} freed with {:?}, but it was allocated with size {} and align {}",
+                ptr, layout, header.size, header.align
+            );
+        }
+
+        let (outer_layout, offset) = outer_layout(layout);
+        unsafe {
+            let block = ptr.sub(offset);
+            let front_guard = slice::from_raw_parts(block, offset - HEADER_SIZE - GUARD_SIZE)
+                .iter()
+                .chain(slice::from_raw_parts(ptr.sub(GUARD_SIZE), GUARD_SIZE));
+            let back_guard = slice::from_raw_parts(ptr.add(layout.size()), GUARD_SIZE);
+
+            if front_guard
+                .chain(back_guard)
+                .any(|&byte| byte != GUARD_BYTE)
+            {
+                panic!(
+                    "heap-debug: red zone of {:p} with {:?} was overwritten",
+                    ptr, layout
+                );
+            }
+
+            header.magic = FREED_MAGIC;
+            ptr.write_bytes(FREE_POISON, layout.size());
+            self.inner.dealloc(block, outer_layout);
+        }
+        self.usage.lock().record_dealloc(layout.size());
+    }
+}
+
+// returns the layout passed to the inner allocator and the offset of the data within it
+fn outer_layout(layout: Layout) -> (Layout, usize) {
+    let align = layout.align().max(mem::align_of::<BlockHeader>());
+    let offset = (HEADER_SIZE + GUARD_SIZE).next_multiple_of(align);
+    let size = offset + layout.size() + GUARD_SIZE;
+
+    (
+        Layout::from_size_align(size, align).expect("heap-debug: layout too large"),
+        offset,
+    )
+}
+
+fn header(ptr: *mut u8) -> *mut BlockHeader {
+    ptr.wrapping_sub(GUARD_SIZE + HEADER_SIZE) as *mut BlockHeader
+}
+
+#[test_case]
+fn test_blocks_are_poisoned_and_guarded() {
+    use crate::allocator::{HeapAllocator, Locked, linked_list::LinkedListAllocator};
+
+    // usize elements keep the arena aligned for ListNode
+    static mut ARENA: [usize; 512] = [0; 512];
+    static INNER: Locked<LinkedListAllocator> = Locked::new(LinkedListAllocator::new());
+
+    unsafe { INNER.lock().init(&raw mut ARENA as usize, 4096) };
+    let allocator = DebugAllocator::new(&INNER);
+
+    let layout = Layout::from_size_align(24, 16).unwrap();
+    let ptr = unsafe { allocator.alloc(layout) };
+    assert_eq!(ptr as usize % 16, 0);
+    assert_eq!(allocator.stats(4096).allocated, 24);
+
+    unsafe {
+        let data = slice::from_raw_parts(ptr, layout.size());
+        assert!(data.iter().all(|&byte| byte == ALLOC_POISON));
+        let back_guard = slice::from_raw_parts(ptr.add(layout.size()), GUARD_SIZE);
+        assert!(back_guard.iter().all(|&byte| byte == GUARD_BYTE));
+
+        ptr.write_bytes(0, layout.size());
+        allocator.dealloc(ptr, layout);
+
+        // the free list node of the inner allocator only overwrites the start of the header
+        assert_eq!((*header(ptr)).magic, FREED_MAGIC);
+        let data = slice::from_raw_parts(ptr, layout.size());
+        assert!(data.iter().all(|&byte| byte == FREE_POISON));
+    }
+
+    assert_eq!(INNER.lock().free_bytes(), 4096);
+    assert_eq!(allocator.stats(4096).allocations, 0);
+}
@@ -1,45 +1,267 @@
 use core::{
     alloc::{GlobalAlloc, Layout},
-    mem,
-    ptr::{self, NonNull},
+    mem, ptr,
 };
 
-use crate::allocator::Locked;
+use crate::allocator::linked_list::LinkedListAllocator;
+use crate::allocator::{HeapAllocator, HeapStats, HeapUsage, Locked, alloc_or_grow};
+use crate::serial_println;
 
-struct BlockListNode {
-    next: Option<&'static mut BlockListNode>,
+const BLOCK_SIZES: &[usize] = &[8, 16, 32, 64, 128, 256, 512, 1024, 2048];
+
+struct FreeBlock {
+    next: *mut FreeBlock,
 }
 
-const BLOCK_SIZES: &[usize] = &[8, 16, 32, 64, 128, 256, 512, 1024, 2048];
+// blocks are carved out of slabs taken from the fallback allocator
+// a slab is aligned to its size so every block finds its slab by masking its address,
+// and the first blocks of the slab hold the header
+// every slab keeps its own free list, and the slabs with a free block are linked both ways, so
+// a slab is found and unlinked without walking the free blocks of its size class
+struct SlabHeader {
+    prev: *mut SlabHeader,
+    next: *mut SlabHeader,
+    free_list: *mut FreeBlock,
+    free_blocks: usize,
+}
+
+const MIN_SLAB_SIZE: usize = 4096;
+const MIN_SLAB_BLOCKS: usize = 8;
+// fully free slabs kept per size class, one more and they go back to the fallback allocator
+const EMPTY_SLABS_KEPT: usize = 1;
 
 pub struct FixedSizeBlockAllocator {
-    list_heads: [Option<&'static mut BlockListNode>; BLOCK_SIZES.len()],
-    fallback_allocator: linked_list_allocator::Heap,
-    // here the custom LinkedListAllocator can be used but it doesn't merge free blocks of memory
+    // per size class, the slabs that have at least one free block
+    free_slabs: [*mut SlabHeader; BLOCK_SIZES.len()],
+    empty_slabs: [usize; BLOCK_SIZES.len()],
+    // merges neighbouring free regions, so it doesn't fragment over time
+    fallback_allocator: LinkedListAllocator,
+    usage: HeapUsage,
 }
 
+// the raw pointers only point into slabs owned by the allocator
+unsafe impl Send for FixedSizeBlockAllocator {}
+
 impl FixedSizeBlockAllocator {
     pub const fn new() -> Self {
-        const EMPTY: Option<&'static mut BlockListNode> = None;
         FixedSizeBlockAllocator {
-            list_heads: [EMPTY; BLOCK_SIZES.len()],
-            fallback_allocator: linked_list_allocator::Heap::empty(),
+            free_slabs: [ptr::null_mut(); BLOCK_SIZES.len()],
+            empty_slabs: [0; BLOCK_SIZES.len()],
+            fallback_allocator: LinkedListAllocator::new(),
+            usage: HeapUsage::new(),
+        }
+    }
+
+    // yields the block size of every size class with the number of its free blocks
+    pub fn free_block_counts(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        BLOCK_SIZES
+            .iter()
+            .zip(self.free_slabs.iter())
+            .map(|(&block_size, &head)| {
+                let mut count = 0;
+                let mut slab = head;
+                while !slab.is_null() {
+                    unsafe {
+                        count += (*slab).free_blocks;
+                        slab = (*slab).next;
+                    }
+                }
+
+                (block_size, count)
+            })
+    }
+
+    fn alloc_block(&mut self, index: usize) -> *mut u8 {
+        if self.free_slabs[index].is_null() && !self.add_slab(index) {
+            return ptr::null_mut();
         }
+
+        let slab = self.free_slabs[index];
+        let header = unsafe { &mut *slab };
+        if header.free_blocks == slab_capacity(index) {
+            self.empty_slabs[index] -= 1;
+        }
+
+        let block = header.free_list;
+        header.free_list = unsafe { (*block).next };
+        header.free_blocks -= 1;
+        if header.free_list.is_null() {
+            unsafe { self.unlink_slab(index, slab) };
+        }
+
+        block as *mut u8
     }
 
-    #[allow(clippy::missing_safety_doc)]
-    pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+    // unsafe because the caller must guarantee that `block` was handed out by this size class
+    unsafe fn dealloc_block(&mut self, block: *mut u8, index: usize) {
+        assert!(mem::size_of::<FreeBlock>() <= BLOCK_SIZES[index]);
+        assert!(mem::align_of::<FreeBlock>() <= BLOCK_SIZES[index]);
+
+        let slab = slab_header(block as usize, index);
+        let header = unsafe { &mut *slab };
+        let was_full = header.free_list.is_null();
+
+        let free_block = block as *mut FreeBlock;
         unsafe {
-            self.fallback_allocator.init(heap_start, heap_size);
+            free_block.write(FreeBlock {
+                next: header.free_list,
+            })
+        };
+        header.free_list = free_block;
+        header.free_blocks += 1;
+        if was_full {
+            unsafe { self.link_slab(index, slab) };
+        }
+
+        if header.free_blocks == slab_capacity(index) {
+            self.empty_slabs[index] += 1;
+            if self.empty_slabs[index] > EMPTY_SLABS_KEPT {
+                unsafe { self.free_slab(index, slab) };
+            }
+        }
+    }
+
+    fn add_slab(&mut self, index: usize) -> bool {
+        let slab = self.fallback_alloc(slab_layout(index));
+        if slab.is_null() {
+            return false;
+        }
+
+        // linked in reverse so that the blocks are handed out in address order
+        let block_size = BLOCK_SIZES[index];
+        let first_block = header_blocks(index);
+        let mut free_list = ptr::null_mut();
+        for block in (first_block..first_block + slab_capacity(index)).rev() {
+            let block = unsafe { slab.add(block * block_size) } as *mut FreeBlock;
+            unsafe { block.write(FreeBlock { next: free_list }) };
+            free_list = block;
+        }
+
+        let slab = slab as *mut SlabHeader;
+        unsafe {
+            slab.write(SlabHeader {
+                prev: ptr::null_mut(),
+                next: ptr::null_mut(),
+                free_list,
+                free_blocks: slab_capacity(index),
+            });
+            self.link_slab(index, slab);
+        }
+        self.empty_slabs[index] += 1;
+
+        true
+    }
+
+    // gives every fully free slab of every size class back to the fallback allocator
+    // this walks the slabs, which is fine since it only runs when the heap is exhausted
+    fn release_empty_slabs(&mut self) -> bool {
+        let mut released = false;
+
+        for index in 0..BLOCK_SIZES.len() {
+            let mut slab = self.free_slabs[index];
+            while !slab.is_null() && self.empty_slabs[index] > 0 {
+                let (next, free_blocks) = unsafe { ((*slab).next, (*slab).free_blocks) };
+                if free_blocks == slab_capacity(index) {
+                    unsafe { self.free_slab(index, slab) };
+                    released = true;
+                }
+                slab = next;
+            }
+        }
+
+        released
+    }
+
+    // unsafe because `slab` has to be a fully free slab of the size class
+    unsafe fn free_slab(&mut self, index: usize, slab: *mut SlabHeader) {
+        unsafe {
+            self.unlink_slab(index, slab);
+            self.fallback_allocator
+                .deallocate(slab as *mut u8, slab_layout(index));
+        }
+        self.empty_slabs[index] -= 1;
+    }
+
+    // unsafe because `slab` has to be a slab of the size class that isn't linked yet
+    unsafe fn link_slab(&mut self, index: usize, slab: *mut SlabHeader) {
+        let head = self.free_slabs[index];
+        unsafe {
+            (*slab).prev = ptr::null_mut();
+            (*slab).next = head;
+            if !head.is_null() {
+                (*head).prev = slab;
+            }
+        }
+        self.free_slabs[index] = slab;
+    }
+
+    // unsafe because `slab` has to be linked into the size class
+    unsafe fn unlink_slab(&mut self, index: usize, slab: *mut SlabHeader) {
+        unsafe {
+            let (prev, next) = ((*slab).prev, (*slab).next);
+            if prev.is_null() {
+                self.free_slabs[index] = next;
+            } else {
+                (*prev).next = next;
+            }
+            if !next.is_null() {
+                (*next).prev = prev;
+            }
         }
     }
 
+    // fully free slabs are reclaimed before the heap has to grow
     fn fallback_alloc(&mut self, layout: Layout) -> *mut u8 {
-        match self.fallback_allocator.allocate_first_fit(layout) {
-            Ok(ptr) => ptr.as_ptr(),
-            Err(_) => ptr::null_mut(),
+        alloc_or_grow(self, layout, |allocator| {
+            let ptr = allocator.fallback_allocator.allocate(layout);
+            if ptr.is_null() && allocator.release_empty_slabs() {
+                allocator.fallback_allocator.allocate(layout)
+            } else {
+                ptr
+            }
+        })
+    }
+}
+
+impl HeapAllocator for FixedSizeBlockAllocator {
+    unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+        unsafe {
+            self.fallback_allocator.init(heap_start, heap_size);
+        }
+    }
+
+    unsafe fn extend(&mut self, size: usize) {
+        unsafe {
+            self.fallback_allocator.extend(size);
         }
     }
+
+    fn heap_end(&self) -> usize {
+        self.fallback_allocator.heap_end()
+    }
+
+    // blocks sitting on a free list count as free, not allocated
+    fn stats(&self) -> HeapStats {
+        self.usage.stats(self.fallback_allocator.heap_size())
+    }
+
+    fn print_diagnostics(&mut self) {
+        for ((block_size, count), empty_slabs) in
+            self.free_block_counts().zip(self.empty_slabs.iter())
+        {
+            serial_println!(
+                "  {} B blocks on free list: {}, empty slabs: {}",
+                block_size,
+                count,
+                empty_slabs
+            );
+        }
+        serial_println!(
+            "  fallback heap: {} B free, largest hole {} B",
+            self.fallback_allocator.free_bytes(),
+            self.fallback_allocator.largest_free_region()
+        );
+    }
 }
 
 impl Default for FixedSizeBlockAllocator {
@@ -52,46 +274,27 @@ unsafe impl GlobalAlloc for Locked<FixedSizeBlockAllocator> {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
         let mut allocator = self.lock();
 
-        match list_index(&layout) {
-            Some(index) => match allocator.list_heads[index].take() {
-                Some(node) => {
-                    allocator.list_heads[index] = node.next.take();
-                    node as *mut BlockListNode as *mut u8
-                }
-                None => {
-                    let block_size = BLOCK_SIZES[index];
-                    let block_align = block_size;
-                    let layout = Layout::from_size_align(block_size, block_align).unwrap();
-                    allocator.fallback_alloc(layout)
-                }
-            },
+        let ptr = match list_index(&layout) {
+            Some(index) => allocator.alloc_block(index),
             None => allocator.fallback_alloc(layout),
+        };
+
+        if !ptr.is_null() {
+            allocator.usage.record_alloc(allocated_size(&layout));
         }
+
+        ptr
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
         let mut allocator = self.lock();
+        allocator.usage.record_dealloc(allocated_size(&layout));
 
         match list_index(&layout) {
-            Some(index) => {
-                let new_node = BlockListNode {
-                    next: allocator.list_heads[index].take(),
-                };
-                assert!(mem::size_of::<BlockListNode>() <= BLOCK_SIZES[index]);
-                assert!(mem::align_of::<BlockListNode>() <= BLOCK_SIZES[index]);
-                let new_node_ptr = ptr as *mut BlockListNode;
-
-                unsafe {
-                    new_node_ptr.write(new_node);
-                    allocator.list_heads[index] = Some(&mut *new_node_ptr);
-                }
-            }
-            None => {
-                let ptr = NonNull::new(ptr).unwrap();
-                unsafe {
-                    allocator.fallback_allocator.deallocate(ptr, layout);
-                }
-            }
+            Some(index) => unsafe { allocator.dealloc_block(ptr, index) },
+            None => unsafe {
+                allocator.fallback_allocator.deallocate(ptr, layout);
+            },
         }
     }
 }
@@ -100,3 +303,69 @@ fn list_index(layout: &Layout) -> Option<usize> {
     let required_block_size = layout.size().max(layout.align());
     BLOCK_SIZES.iter().position(|&s| s >= required_block_size)
 }
+
+// the number of heap bytes that are actually taken by an allocation of this layout
+fn allocated_size(layout: &Layout) -> usize {
+    match list_index(layout) {
+        Some(index) => BLOCK_SIZES[index],
+        None => layout.size(),
+    }
+}
+
+fn slab_layout(index: usize) -> Layout {
+    let slab_size = (BLOCK_SIZES[index] * MIN_SLAB_BLOCKS).max(MIN_SLAB_SIZE);
+    Layout::from_size_align(slab_size, slab_size).unwrap()
+}
+
+// the number of blocks at the start of a slab that the header takes
+fn header_blocks(index: usize) -> usize {
+    mem::size_of::<SlabHeader>().div_ceil(BLOCK_SIZES[index])
+}
+
+// the number of blocks in a slab, without the ones taken by the header
+fn slab_capacity(index: usize) -> usize {
+    slab_layout(index).size() / BLOCK_SIZES[index] - header_blocks(index)
+}
+
+fn slab_header(block: usize, index: usize) -> *mut SlabHeader {
+    (block & !(slab_layout(index).size() - 1)) as *mut SlabHeader
+}
+
+#[test_case]
+fn test_empty_slabs_return_to_fallback() {
+    const ARENA_SIZE: usize = 32 * 1024;
+    // usize elements keep the arena aligned for the fallback ListNode
+    static mut ARENA: [usize; ARENA_SIZE / 8] = [0; ARENA_SIZE / 8];
+
+    let allocator = Locked::new(FixedSizeBlockAllocator::new());
+    unsafe { allocator.lock().init(&raw mut ARENA as usize, ARENA_SIZE) };
+
+    // one block more than a slab holds, so freeing them all leaves two empty slabs
+    let layout = Layout::from_size_align(8, 8).unwrap();
+    let mut blocks = [ptr::null_mut(); 512];
+    let blocks = &mut blocks[..slab_capacity(0) + 1];
+    for block in blocks.iter_mut() {
+        *block = unsafe { allocator.alloc(layout) };
+        assert!(!block.is_null());
+    }
+    for &block in blocks.iter() {
+        unsafe { allocator.dealloc(block, layout) };
+    }
+
+    // the second empty slab went back to the fallback allocator right away
+    assert_eq!(allocator.lock().empty_slabs[0], EMPTY_SLABS_KEPT);
+    assert_eq!(
+        allocator.lock().free_block_counts().next(),
+        Some((8, slab_capacity(0)))
+    );
+    assert_eq!(
+        allocator.lock().fallback_allocator.free_bytes(),
+        ARENA_SIZE - MIN_SLAB_SIZE
+    );
+
+    // the kept slab is reclaimed once the fallback allocator can't serve the whole arena
+    let whole_arena = Layout::from_size_align(ARENA_SIZE, 8).unwrap();
+    let ptr = unsafe { allocator.alloc(whole_arena) };
+    assert_eq!(ptr as usize, &raw mut ARENA as usize);
+    assert_eq!(allocator.lock().empty_slabs[0], 0);
+}
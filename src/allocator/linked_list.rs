@@ -1,9 +1,11 @@
-use crate::allocator::Locked;
+use crate::allocator::{HeapAllocator, HeapStats, HeapUsage, Locked, alloc_or_grow};
+use crate::serial_println;
 
 use super::align_up;
 use core::{
     alloc::{GlobalAlloc, Layout},
-    mem, ptr,
+    cmp::Ordering,
+    iter, mem, ptr,
 };
 
 struct ListNode {
@@ -25,49 +27,132 @@ impl ListNode {
     }
 }
 
+// the free list is kept sorted by address so that neighbouring free regions can be merged
 pub struct LinkedListAllocator {
     head: ListNode,
+    heap_start: usize,
+    heap_end: usize,
+    usage: HeapUsage,
 }
 
 impl LinkedListAllocator {
     pub const fn new() -> Self {
         LinkedListAllocator {
             head: ListNode::new(0),
+            heap_start: 0,
+            heap_end: 0,
+            usage: HeapUsage::new(),
         }
     }
 
-    #[allow(clippy::missing_safety_doc)]
-    pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
-        unsafe {
-            self.add_free_region(heap_start, heap_size);
+    // first fit allocation without growing the heap, returns null when no region fits
+    pub fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        let (size, align) = Self::size_align(layout);
+
+        match self.find_region(size, align) {
+            Some((region_start, region_end, alloc_start)) => {
+                let alloc_end = alloc_start.checked_add(size).expect("overflow");
+
+                unsafe {
+                    if alloc_start > region_start {
+                        self.add_free_region(region_start, alloc_start - region_start);
+                    }
+                    if region_end > alloc_end {
+                        self.add_free_region(alloc_end, region_end - alloc_end);
+                    }
+                }
+
+                alloc_start as *mut u8
+            }
+            None => ptr::null_mut(),
         }
     }
 
+    // unsafe because the caller must guarantee that `ptr` was allocated with the same layout
+    #[allow(clippy::missing_safety_doc)]
+    pub unsafe fn deallocate(&mut self, ptr: *mut u8, layout: Layout) {
+        let (size, _) = Self::size_align(layout);
+
+        unsafe { self.add_free_region(ptr as usize, size) }
+    }
+
+    pub fn heap_size(&self) -> usize {
+        self.heap_end - self.heap_start
+    }
+
+    pub fn free_bytes(&self) -> usize {
+        self.regions().map(|region| region.size).sum()
+    }
+
+    pub fn free_region_count(&self) -> usize {
+        self.regions().count()
+    }
+
+    pub fn largest_free_region(&self) -> usize {
+        self.regions().map(|region| region.size).max().unwrap_or(0)
+    }
+
+    fn regions(&self) -> impl Iterator<Item = &ListNode> {
+        let mut current = self.head.next.as_deref();
+
+        iter::from_fn(move || {
+            let region = current?;
+            current = region.next.as_deref();
+            Some(region)
+        })
+    }
+
     unsafe fn add_free_region(&mut self, addr: usize, size: usize) {
         // ensure that the freed region is capable of holding ListNode
         assert_eq!(align_up(addr, mem::align_of::<ListNode>()), addr);
         assert!(size >= mem::size_of::<ListNode>());
 
-        let mut node = ListNode::new(size);
-        node.next = self.head.next.take();
-        let node_ptr = addr as *mut ListNode;
+        // the head is a dummy node outside of the heap, it must never be merged with a region
+        let head_addr = self.head.start_addr();
+        let mut current = &mut self.head;
+        while current
+            .next
+            .as_ref()
+            .is_some_and(|next| next.start_addr() < addr)
+        {
+            current = current.next.as_mut().unwrap();
+        }
+
+        let mut size = size;
+        let mut next = current.next.take();
+
+        // merge with the region right after the freed one
+        if let Some(following) = next.as_mut()
+            && addr + size == following.start_addr()
+        {
+            size += following.size;
+            next = following.next.take();
+        }
+
+        // merge with the region right before the freed one
+        if current.start_addr() != head_addr && current.end_addr() == addr {
+            current.size += size;
+            current.next = next;
+            return;
+        }
 
+        let node_ptr = addr as *mut ListNode;
         unsafe {
-            node_ptr.write(node);
-            self.head.next = Some(&mut *node_ptr);
+            node_ptr.write(ListNode { size, next });
+            current.next = Some(&mut *node_ptr);
         }
     }
 
-    fn find_region(&mut self, size: usize, align: usize) -> Option<(&'static mut ListNode, usize)> {
+    // removes the first fitting region from the list and returns its bounds and the allocation start
+    fn find_region(&mut self, size: usize, align: usize) -> Option<(usize, usize, usize)> {
         let mut current = &mut self.head;
 
         while let Some(ref mut region) = current.next {
             if let Ok(alloc_start) = Self::alloc_from_region(region, size, align) {
-                let next = region.next.take();
-                let ret = Some((current.next.take().unwrap(), alloc_start));
+                let bounds = (region.start_addr(), region.end_addr(), alloc_start);
+                current.next = region.next.take();
 
-                current.next = next;
-                return ret;
+                return Some(bounds);
             } else {
                 current = current.next.as_mut().unwrap();
             }
@@ -77,7 +162,14 @@ impl LinkedListAllocator {
     }
 
     fn alloc_from_region(region: &ListNode, size: usize, align: usize) -> Result<usize, ()> {
-        let alloc_start = align_up(region.start_addr(), align);
+        let mut alloc_start = align_up(region.start_addr(), align);
+
+        // the padding in front of the allocation goes back to the free list, so it must fit a ListNode
+        let padding = alloc_start - region.start_addr();
+        if padding > 0 && padding < mem::size_of::<ListNode>() {
+            alloc_start = align_up(region.start_addr() + mem::size_of::<ListNode>(), align);
+        }
+
         let alloc_end = alloc_start.checked_add(size).ok_or(())?;
 
         if alloc_end > region.end_addr() {
@@ -93,6 +185,59 @@ impl LinkedListAllocator {
         Ok(alloc_start)
     }
 
+    // takes the start of the free region directly after the allocation, if there is one big enough
+    fn grow_in_place(&mut self, addr: usize, old_size: usize, new_size: usize) -> bool {
+        let alloc_end = addr + old_size;
+        let needed = new_size - old_size;
+
+        let mut current = &mut self.head;
+        while current
+            .next
+            .as_ref()
+            .is_some_and(|next| next.start_addr() < alloc_end)
+        {
+            current = current.next.as_mut().unwrap();
+        }
+
+        let Some(next) = current.next.as_mut() else {
+            return false;
+        };
+        if next.start_addr() != alloc_end || next.size < needed {
+            return false;
+        }
+
+        let excess_size = next.size - needed;
+        if excess_size > 0 && excess_size < mem::size_of::<ListNode>() {
+            return false;
+        }
+
+        let following = next.next.take();
+        if excess_size == 0 {
+            current.next = following;
+        } else {
+            let node_ptr = (alloc_end + needed) as *mut ListNode;
+            unsafe {
+                node_ptr.write(ListNode {
+                    size: excess_size,
+                    next: following,
+                });
+                current.next = Some(&mut *node_ptr);
+            }
+        }
+
+        true
+    }
+
+    fn shrink_in_place(&mut self, addr: usize, old_size: usize, new_size: usize) -> bool {
+        let excess_size = old_size - new_size;
+        if excess_size < mem::size_of::<ListNode>() {
+            return false;
+        }
+
+        unsafe { self.add_free_region(addr + new_size, excess_size) };
+        true
+    }
+
     fn size_align(layout: Layout) -> (usize, usize) {
         let layout = layout
             .align_to(mem::align_of::<ListNode>())
@@ -111,30 +256,135 @@ impl Default for LinkedListAllocator {
     }
 }
 
+impl HeapAllocator for LinkedListAllocator {
+    unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+        self.heap_start = heap_start;
+        self.heap_end = heap_start + heap_size;
+        unsafe {
+            self.add_free_region(heap_start, heap_size);
+        }
+    }
+
+    unsafe fn extend(&mut self, size: usize) {
+        unsafe {
+            self.add_free_region(self.heap_end, size);
+        }
+        self.heap_end += size;
+    }
+
+    fn heap_end(&self) -> usize {
+        self.heap_end
+    }
+
+    fn stats(&self) -> HeapStats {
+        self.usage.stats(self.heap_size())
+    }
+
+    fn print_diagnostics(&mut self) {
+        serial_println!(
+            "  free list: {} regions, {} B free, largest region {} B",
+            self.free_region_count(),
+            self.free_bytes(),
+            self.largest_free_region()
+        );
+    }
+}
+
 unsafe impl GlobalAlloc for Locked<LinkedListAllocator> {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        let (size, align) = LinkedListAllocator::size_align(layout);
         let mut allocator = self.lock();
 
-        if let Some((region, alloc_start)) = allocator.find_region(size, align) {
-            let alloc_end = alloc_start.checked_add(size).expect("overflow");
-            let excess_size = region.end_addr() - alloc_end;
+        let ptr = alloc_or_grow(&mut *allocator, layout, |allocator| {
+            allocator.allocate(layout)
+        });
 
-            if excess_size > 0 {
-                unsafe {
-                    allocator.add_free_region(alloc_end, excess_size);
+        if !ptr.is_null() {
+            let (size, _) = LinkedListAllocator::size_align(layout);
+            allocator.usage.record_alloc(size);
+        }
+
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let (size, _) = LinkedListAllocator::size_align(layout);
+        let mut allocator = self.lock();
+
+        allocator.usage.record_dealloc(size);
+        unsafe { allocator.deallocate(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_layout = unsafe { Layout::from_size_align_unchecked(new_size, layout.align()) };
+        let (old_block_size, _) = LinkedListAllocator::size_align(layout);
+        let (new_block_size, _) = LinkedListAllocator::size_align(new_layout);
+
+        {
+            let mut allocator = self.lock();
+            let resized = match new_block_size.cmp(&old_block_size) {
+                Ordering::Equal => true,
+                Ordering::Greater => {
+                    allocator.grow_in_place(ptr as usize, old_block_size, new_block_size)
+                }
+                Ordering::Less => {
+                    allocator.shrink_in_place(ptr as usize, old_block_size, new_block_size)
                 }
+            };
+
+            if resized {
+                allocator.usage.record_dealloc(old_block_size);
+                allocator.usage.record_alloc(new_block_size);
+                return ptr;
             }
+        }
 
-            alloc_start as *mut u8
-        } else {
-            ptr::null_mut()
+        // the allocation can't be resized where it is, so it is moved like the default realloc does
+        let new_ptr = unsafe { self.alloc(new_layout) };
+        if !new_ptr.is_null() {
+            unsafe {
+                ptr::copy_nonoverlapping(ptr, new_ptr, layout.size().min(new_size));
+                self.dealloc(ptr, layout);
+            }
         }
+
+        new_ptr
     }
+}
 
-    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
-        let (size, _) = LinkedListAllocator::size_align(layout);
+#[test_case]
+fn test_free_neighbours_are_merged() {
+    // usize elements keep the arena aligned for ListNode
+    static mut ARENA: [usize; 512] = [0; 512];
+
+    let mut allocator = LinkedListAllocator::new();
+    unsafe { allocator.init(&raw mut ARENA as usize, 4096) };
+
+    let layout = Layout::from_size_align(1024, 8).unwrap();
+    let blocks = [(); 4].map(|_| allocator.allocate(layout));
+    assert!(blocks.iter().all(|block| !block.is_null()));
+    assert!(allocator.allocate(layout).is_null());
 
-        unsafe { self.lock().add_free_region(ptr as usize, size) }
+    for index in [0, 2, 1, 3] {
+        unsafe { allocator.deallocate(blocks[index], layout) };
     }
+
+    assert_eq!(allocator.free_region_count(), 1);
+    assert_eq!(allocator.largest_free_region(), 4096);
+}
+
+#[test_case]
+fn test_grow_in_place() {
+    // usize elements keep the arena aligned for ListNode
+    static mut ARENA: [usize; 512] = [0; 512];
+
+    let mut allocator = LinkedListAllocator::new();
+    unsafe { allocator.init(&raw mut ARENA as usize, 4096) };
+
+    let layout = Layout::from_size_align(1024, 8).unwrap();
+    let first = allocator.allocate(layout);
+    assert!(allocator.grow_in_place(first as usize, 1024, 2048));
+
+    let second = allocator.allocate(layout);
+    assert_eq!(second as usize, first as usize + 2048);
+    assert!(!allocator.grow_in_place(first as usize, 2048, 3072));
 }
@@ -0,0 +1,309 @@
+use core::{
+    fmt,
+    marker::PhantomData,
+    mem,
+    ops::{Deref, DerefMut},
+    ptr::{self, NonNull},
+};
+
+use spin::Mutex;
+use x86_64::{
+    PhysAddr, VirtAddr,
+    structures::paging::{PhysFrame, Size4KiB},
+};
+
+use crate::memory;
+
+const FRAME_SIZE: usize = 4096;
+// fully free slabs kept per cache, one more and its frames go back to the frame allocator
+const EMPTY_SLABS_KEPT: usize = 1;
+
+struct FreeObject {
+    next: *mut FreeObject,
+}
+
+// lives at the start of every slab, the objects follow it
+struct SlabHeader {
+    next: *mut SlabHeader,
+    free_list: *mut FreeObject,
+    in_use: usize,
+}
+
+struct CacheState {
+    slabs: *mut SlabHeader,
+    slab_count: usize,
+    empty_slabs: usize,
+    in_use: usize,
+    high_water_mark: usize,
+    allocations: usize,
+    frees: usize,
+}
+
+// the raw pointers only point into slabs owned by the cache
+unsafe impl Send for CacheState {}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SlabCacheStats {
+    pub name: &'static str,
+    pub object_size: usize,
+    pub objects_per_slab: usize,
+    pub slabs: usize,
+    pub objects_in_use: usize,
+    pub high_water_mark: usize,
+    pub allocations: usize,
+    pub frees: usize,
+}
+
+// named cache of equally sized objects of one type, backed by whole frames instead of the heap
+// caches are meant to be statics, e.g.
+// `static TASK_CACHE: SlabCache<Task> = SlabCache::new("task");`
+pub struct SlabCache<T> {
+    name: &'static str,
+    constructor: Option<fn() -> T>,
+    state: Mutex<CacheState>,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T> SlabCache<T> {
+    // every object slot is also big enough to hold the free list link
+    const SLOT_ALIGN: usize = max(mem::align_of::<T>(), mem::align_of::<FreeObject>());
+    const SLOT_SIZE: usize = align_up(
+        max(mem::size_of::<T>(), mem::size_of::<FreeObject>()),
+        Self::SLOT_ALIGN,
+    );
+    const OBJECTS_OFFSET: usize = align_up(mem::size_of::<SlabHeader>(), Self::SLOT_ALIGN);
+    // slabs are aligned to their size, so an object finds its slab by masking its address
+    const SLAB_FRAMES: usize = {
+        assert!(
+            Self::SLOT_ALIGN <= FRAME_SIZE,
+            "slab objects can't be aligned beyond a frame"
+        );
+        (Self::OBJECTS_OFFSET + Self::SLOT_SIZE)
+            .div_ceil(FRAME_SIZE)
+            .next_power_of_two()
+    };
+    const SLAB_SIZE: usize = Self::SLAB_FRAMES * FRAME_SIZE;
+    const OBJECTS_PER_SLAB: usize = (Self::SLAB_SIZE - Self::OBJECTS_OFFSET) / Self::SLOT_SIZE;
+
+    pub const fn new(name: &'static str) -> Self {
+        SlabCache {
+            name,
+            constructor: None,
+            state: Mutex::new(CacheState {
+                slabs: ptr::null_mut(),
+                slab_count: 0,
+                empty_slabs: 0,
+                in_use: 0,
+                high_water_mark: 0,
+                allocations: 0,
+                frees: 0,
+            }),
+            _marker: PhantomData,
+        }
+    }
+
+    // the constructor builds the objects handed out by `alloc_constructed`
+    pub const fn with_constructor(name: &'static str, constructor: fn() -> T) -> Self {
+        let mut cache = Self::new(name);
+        cache.constructor = Some(constructor);
+
+        cache
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    // returns `None` when no frame is left for a new slab
+    pub fn alloc(&'static self, value: T) -> Option<SlabBox<T>> {
+        let object = self.alloc_slot()?;
+        unsafe { object.as_ptr().write(value) };
+
+        Some(SlabBox {
+            object,
+            cache: self,
+        })
+    }
+
+    pub fn alloc_constructed(&'static self) -> Option<SlabBox<T>> {
+        let constructor = self
+            .constructor
+            .unwrap_or_else(|| panic!("slab cache {} has no constructor", self.name));
+
+        self.alloc(constructor())
+    }
+
+    pub fn stats(&self) -> SlabCacheStats {
+        let state = self.state.lock();
+
+        SlabCacheStats {
+            name: self.name,
+            object_size: Self::SLOT_SIZE,
+            objects_per_slab: Self::OBJECTS_PER_SLAB,
+            slabs: state.slab_count,
+            objects_in_use: state.in_use,
+            high_water_mark: state.high_water_mark,
+            allocations: state.allocations,
+            frees: state.frees,
+        }
+    }
+
+    fn alloc_slot(&self) -> Option<NonNull<T>> {
+        let mut state = self.state.lock();
+
+        let mut slab = state.slabs;
+        while !slab.is_null() && unsafe { (*slab).free_list.is_null() } {
+            slab = unsafe { (*slab).next };
+        }
+        if slab.is_null() {
+            slab = Self::add_slab(&mut state)?;
+        }
+
+        let header = unsafe { &mut *slab };
+        if header.in_use == 0 {
+            state.empty_slabs -= 1;
+        }
+        let object = header.free_list;
+        header.free_list = unsafe { (*object).next };
+        header.in_use += 1;
+
+        state.in_use += 1;
+        state.allocations += 1;
+        state.high_water_mark = state.high_water_mark.max(state.in_use);
+
+        NonNull::new(object as *mut T)
+    }
+
+    // unsafe because the caller must guarantee that the object was allocated by this cache
+    // and that it has already been dropped
+    unsafe fn free_slot(&self, object: NonNull<T>) {
+        let mut state = self.state.lock();
+
+        let slab = (object.as_ptr() as usize & !(Self::SLAB_SIZE - 1)) as *mut SlabHeader;
+        let header = unsafe { &mut *slab };
+        let free_object = object.as_ptr() as *mut FreeObject;
+        unsafe {
+            free_object.write(FreeObject {
+                next: header.free_list,
+            })
+        };
+        header.free_list = free_object;
+        header.in_use -= 1;
+
+        state.in_use -= 1;
+        state.frees += 1;
+
+        if header.in_use == 0 {
+            state.empty_slabs += 1;
+            if state.empty_slabs > EMPTY_SLABS_KEPT {
+                Self::release_slab(&mut state, slab);
+            }
+        }
+    }
+
+    fn add_slab(state: &mut CacheState) -> Option<*mut SlabHeader> {
+        let slab: *mut SlabHeader = {
+            let mut kernel_memory = memory::KERNEL_MEMORY.lock();
+            let kernel_memory = kernel_memory.as_mut()?;
+            let frames = kernel_memory
+                .frame_allocator
+                .allocate_contiguous(Self::SLAB_FRAMES, Self::SLAB_FRAMES)?;
+
+            // the complete physical memory is mapped at the offset, so no new mapping is needed
+            let phys_offset = kernel_memory.mapper.phys_offset();
+            (phys_offset + frames.start.start_address().as_u64()).as_mut_ptr()
+        };
+
+        let mut free_list = ptr::null_mut();
+        for index in (0..Self::OBJECTS_PER_SLAB).rev() {
+            let object =
+                (slab as usize + Self::OBJECTS_OFFSET + index * Self::SLOT_SIZE) as *mut FreeObject;
+            unsafe { object.write(FreeObject { next: free_list }) };
+            free_list = object;
+        }
+
+        unsafe {
+            slab.write(SlabHeader {
+                next: state.slabs,
+                free_list,
+                in_use: 0,
+            });
+        }
+        state.slabs = slab;
+        state.slab_count += 1;
+        state.empty_slabs += 1;
+
+        Some(slab)
+    }
+
+    fn release_slab(state: &mut CacheState, slab: *mut SlabHeader) {
+        let mut link = &mut state.slabs;
+        while *link != slab {
+            link = unsafe { &mut (**link).next };
+        }
+        *link = unsafe { (*slab).next };
+        state.slab_count -= 1;
+        state.empty_slabs -= 1;
+
+        let mut kernel_memory = memory::KERNEL_MEMORY.lock();
+        let kernel_memory = kernel_memory
+            .as_mut()
+            .expect("kernel memory is not initialized");
+        let phys_offset = kernel_memory.mapper.phys_offset();
+        let start: PhysFrame<Size4KiB> = PhysFrame::containing_address(PhysAddr::new(
+            VirtAddr::from_ptr(slab).as_u64() - phys_offset.as_u64(),
+        ));
+
+        unsafe {
+            kernel_memory
+                .frame_allocator
+                .deallocate_contiguous(PhysFrame::range(start, start + Self::SLAB_FRAMES as u64));
+        }
+    }
+}
+
+// owning pointer to an object in a slab cache, the slot goes back to the cache on drop
+pub struct SlabBox<T: 'static> {
+    object: NonNull<T>,
+    cache: &'static SlabCache<T>,
+}
+
+unsafe impl<T: Send> Send for SlabBox<T> {}
+unsafe impl<T: Sync> Sync for SlabBox<T> {}
+
+impl<T> Deref for SlabBox<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { self.object.as_ref() }
+    }
+}
+
+impl<T> DerefMut for SlabBox<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { self.object.as_mut() }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for SlabBox<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T> Drop for SlabBox<T> {
+    fn drop(&mut self) {
+        unsafe {
+            ptr::drop_in_place(self.object.as_ptr());
+            self.cache.free_slot(self.object);
+        }
+    }
+}
+
+const fn max(a: usize, b: usize) -> usize {
+    if a > b { a } else { b }
+}
+
+const fn align_up(addr: usize, align: usize) -> usize {
+    (addr + align - 1) & !(align - 1)
+}
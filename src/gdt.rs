@@ -1,7 +1,8 @@
 use lazy_static::lazy_static;
+use spin::Mutex;
 use x86_64::{
     VirtAddr,
-    instructions::tables::load_tss,
+    instructions::{interrupts, tables::load_tss},
     registers::segmentation::{CS, Segment},
     structures::{
         gdt::{Descriptor, GlobalDescriptorTable, SegmentSelector},
@@ -9,9 +10,26 @@ use x86_64::{
     },
 };
 
+use crate::memory::stack::{self, KernelStack};
+
 pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
+const IST_STACK_PAGES: usize = 5;
+
+// the double fault handler runs on this until `init_ist_stacks` moves it to a guard-paged stack,
+// it has no guard page, so overflowing it corrupts the statics next to it
+const BOOT_STACK_SIZE: usize = 4096 * IST_STACK_PAGES;
+static mut BOOT_DOUBLE_FAULT_STACK: [u8; BOOT_STACK_SIZE] = [0; BOOT_STACK_SIZE];
+
+// only accessed through raw pointers, the CPU reads the IST entries from it on every stack switch
+static mut TSS: TaskStateSegment = TaskStateSegment::new();
+
+// keeps the guard-paged IST stacks mapped while the TSS points to them
+static IST_STACKS: Mutex<Option<KernelStack>> = Mutex::new(None);
 
 pub fn init() {
+    let boot_stack_start = VirtAddr::from_ptr(&raw const BOOT_DOUBLE_FAULT_STACK);
+    set_ist_entry(DOUBLE_FAULT_IST_INDEX, boot_stack_start + BOOT_STACK_SIZE);
+
     GDT.0.load();
     unsafe {
         CS::set_reg(GDT.1.code_selector);
@@ -23,7 +41,8 @@ lazy_static! {
     static ref GDT: (GlobalDescriptorTable, Selectors) = {
         let mut gdt = GlobalDescriptorTable::new();
         let code_selector = gdt.add_entry(Descriptor::kernel_code_segment());
-        let tss_selector = gdt.add_entry(Descriptor::tss_segment(&TSS));
+        let tss_selector =
+            gdt.add_entry(unsafe { Descriptor::tss_segment_unchecked(&raw const TSS) });
 
         (
             gdt,
@@ -40,17 +59,18 @@ struct Selectors {
     tss_selector: SegmentSelector,
 }
 
-lazy_static! {
-    static ref TSS: TaskStateSegment = {
-        let mut tss = TaskStateSegment::new();
-        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
-            const STACK_SIZE: usize = 4096 * 5;
-            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
-            let stack_start = VirtAddr::from_ptr(&raw const STACK);
+// moves the IST stacks to guard-paged kernel stacks, `memory::init_kernel_memory` must run first
+pub fn init_ist_stacks() {
+    let double_fault_stack = stack::allocate_kernel_stack(IST_STACK_PAGES)
+        .expect("allocating the double fault stack failed");
+    set_ist_entry(DOUBLE_FAULT_IST_INDEX, double_fault_stack.top());
 
-            stack_start + STACK_SIZE
-        };
+    // a stack set up by an earlier call is only dropped once the TSS no longer points to it
+    *IST_STACKS.lock() = Some(double_fault_stack);
+}
 
-        tss
-    };
+fn set_ist_entry(index: u16, stack_top: VirtAddr) {
+    interrupts::without_interrupts(|| unsafe {
+        TSS.interrupt_stack_table[index as usize] = stack_top;
+    });
 }
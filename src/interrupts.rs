@@ -2,7 +2,16 @@ use lazy_static::lazy_static;
 use pic8259::ChainedPics;
 use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
 
-use crate::{gdt, hlt_loop, println};
+use crate::memory::{cow, vma};
+use crate::{gdt, println, serial_println};
+
+pub mod apic;
+pub mod controller;
+pub mod ioapic;
+mod page_fault;
+
+pub use controller::{enable_apic, end_of_interrupt};
+pub use page_fault::{Access, PageFaultReport};
 
 // range 32-47 is chosen because there are the first free number after 32 exception slots
 pub const PIC_1_OFFSET: u8 = 32;
@@ -16,13 +25,25 @@ pub static PICS: spin::Mutex<ChainedPics> =
 pub enum InterruptIndex {
     Timer = PIC_1_OFFSET,
     Keyboard,
+    // only raised once the APIC is enabled
+    LocalApicTimer = PIC_2_OFFSET + 8,
+    Spurious = 0xff,
 }
 
 impl InterruptIndex {
-    fn as_u8(self) -> u8 {
+    pub fn as_u8(self) -> u8 {
         self as u8
     }
 
+    // the ISA IRQ line of the device, the IOAPIC is programmed from it
+    pub fn isa_irq(self) -> Option<u8> {
+        match self {
+            InterruptIndex::Timer => Some(0),
+            InterruptIndex::Keyboard => Some(1),
+            InterruptIndex::LocalApicTimer | InterruptIndex::Spurious => None,
+        }
+    }
+
     fn as_usize(self) -> usize {
         usize::from(self.as_u8())
     }
@@ -41,6 +62,9 @@ lazy_static! {
         }
         idt[InterruptIndex::Timer.as_usize()].set_handler_fn(timer_interrupt_handler);
         idt[InterruptIndex::Keyboard.as_usize()].set_handler_fn(keyboard_interrupt_handler);
+        idt[InterruptIndex::LocalApicTimer.as_usize()]
+            .set_handler_fn(local_apic_timer_interrupt_handler);
+        idt[InterruptIndex::Spurious.as_usize()].set_handler_fn(spurious_interrupt_handler);
         idt.page_fault.set_handler_fn(page_fault_handler);
 
         idt
@@ -53,20 +77,44 @@ extern "x86-interrupt" fn page_fault_handler(
 ) {
     use x86_64::registers::control::Cr2;
 
-    println!("EXCEPTION: PAGE FAULT");
-    println!("Accessed Address: {:?}", Cr2::read());
-    println!("Error Code: {:?}", error_code);
-    println!("{:#?}", stack_frame);
-    hlt_loop();
+    let addr = Cr2::read();
+
+    // the first access to a lazily backed page, the faulting instruction is retried once it's mapped
+    if !error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION)
+        && vma::handle_lazy_fault(addr)
+    {
+        return;
+    }
+
+    // a write to a page that shares its frame, it gets a copy and the instruction is retried
+    if error_code
+        .contains(PageFaultErrorCode::PROTECTION_VIOLATION | PageFaultErrorCode::CAUSED_BY_WRITE)
+        && cow::handle_cow_fault(addr)
+    {
+        return;
+    }
+
+    let report = PageFaultReport {
+        addr,
+        error_code,
+        region: vma::try_find(addr),
+        stack_frame: &stack_frame,
+    };
+    println!("EXCEPTION: PAGE FAULT\n{}", report);
+    serial_println!("EXCEPTION: PAGE FAULT\n{}", report);
+
+    // panicking instead of halting lets the panic handler end test runs right away
+    panic!(
+        "unhandled page fault at {:#x}: {}",
+        addr.as_u64(),
+        report.access()
+    );
 }
 
 extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFrame) {
-    // print!(".");
+    crate::time::tick();
 
-    unsafe {
-        PICS.lock()
-            .notify_end_of_interrupt(InterruptIndex::Timer.as_u8());
-    }
+    end_of_interrupt(InterruptIndex::Timer);
 }
 
 extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStackFrame) {
@@ -76,12 +124,19 @@ extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStac
     let scancode: u8 = unsafe { port.read() };
     crate::task::keyboard::add_scancode(scancode);
 
-    unsafe {
-        PICS.lock()
-            .notify_end_of_interrupt(InterruptIndex::Keyboard.as_u8());
-    }
+    end_of_interrupt(InterruptIndex::Keyboard);
 }
 
+extern "x86-interrupt" fn local_apic_timer_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    apic::timer_tick();
+
+    end_of_interrupt(InterruptIndex::LocalApicTimer);
+}
+
+// the APIC raises it when an interrupt goes away before the CPU takes it, it must not be
+// acknowledged
+extern "x86-interrupt" fn spurious_interrupt_handler(_stack_frame: InterruptStackFrame) {}
+
 pub fn init_idt() {
     IDT.load();
 }
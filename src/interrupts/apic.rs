@@ -0,0 +1,172 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use x86_64::{PhysAddr, registers::model_specific::Msr};
+
+use crate::memory::{self, mmio::MmioRegion, vma::VmaError};
+
+const IA32_APIC_BASE: u32 = 0x1b;
+const APIC_BASE_ENABLE: u64 = 1 << 11;
+const APIC_BASE_ADDR_MASK: u64 = 0x000f_ffff_ffff_f000;
+
+// register offsets into the 4 KiB register page, every register is 32 bits at a 16 byte stride
+const ID: usize = 0x20;
+const VERSION: usize = 0x30;
+const TASK_PRIORITY: usize = 0x80;
+const EOI: usize = 0xb0;
+const SPURIOUS: usize = 0xf0;
+const LVT_TIMER: usize = 0x320;
+const LVT_LINT0: usize = 0x350;
+const LVT_LINT1: usize = 0x360;
+const LVT_ERROR: usize = 0x370;
+const TIMER_INITIAL_COUNT: usize = 0x380;
+const TIMER_CURRENT_COUNT: usize = 0x390;
+const TIMER_DIVIDE: usize = 0x3e0;
+const REGISTERS_SIZE: usize = 0x400;
+
+const SOFTWARE_ENABLE: u32 = 1 << 8;
+const LVT_MASKED: u32 = 1 << 16;
+const LVT_TIMER_PERIODIC: u32 = 1 << 17;
+
+// timer interrupts the local APIC delivered since boot
+static TIMER_INTERRUPTS: AtomicU64 = AtomicU64::new(0);
+
+// the local APIC is there on every x86_64 CPU unless the firmware turned it off, CPUID 1
+// reports it in bit 9 of EDX
+pub fn is_supported() -> bool {
+    use core::arch::x86_64::__cpuid;
+
+    __cpuid(1).edx & (1 << 9) != 0
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimerMode {
+    OneShot,
+    Periodic,
+}
+
+// the timer counts down at the bus clock divided by this
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum TimerDivide {
+    By1 = 0b1011,
+    By2 = 0b0000,
+    By4 = 0b0001,
+    By8 = 0b0010,
+    By16 = 0b0011,
+    By32 = 0b1000,
+    By64 = 0b1001,
+    By128 = 0b1010,
+}
+
+// the local APIC of the CPU, through its memory mapped registers
+// the registers are independent of each other, so writing one doesn't need a lock, which lets
+// interrupt handlers signal the end of an interrupt at any time
+#[derive(Debug)]
+pub struct LocalApic {
+    registers: MmioRegion,
+}
+
+impl LocalApic {
+    // maps the registers where IA32_APIC_BASE puts them and turns the APIC on globally,
+    // it doesn't accept interrupts until `enable` is called
+    pub fn map() -> Result<LocalApic, VmaError> {
+        let mut base = Msr::new(IA32_APIC_BASE);
+        let value = unsafe { base.read() };
+        if value & APIC_BASE_ENABLE == 0 {
+            unsafe { base.write(value | APIC_BASE_ENABLE) };
+        }
+
+        let registers =
+            memory::map_mmio(PhysAddr::new(value & APIC_BASE_ADDR_MASK), REGISTERS_SIZE)?;
+        Ok(LocalApic { registers })
+    }
+
+    // accepts interrupts of every priority and sends the ones nobody claims to `spurious_vector`
+    // LINT0 and LINT1 are masked, the 8259 is not wired through the APIC
+    pub fn enable(&self, spurious_vector: u8) {
+        self.write(TASK_PRIORITY, 0);
+        self.write(LVT_LINT0, LVT_MASKED);
+        self.write(LVT_LINT1, LVT_MASKED);
+        self.write(LVT_ERROR, LVT_MASKED);
+        self.write(SPURIOUS, SOFTWARE_ENABLE | u32::from(spurious_vector));
+    }
+
+    pub fn id(&self) -> u8 {
+        (self.read(ID) >> 24) as u8
+    }
+
+    pub fn version(&self) -> u8 {
+        self.read(VERSION) as u8
+    }
+
+    pub fn end_of_interrupt(&self) {
+        self.write(EOI, 0);
+    }
+
+    // starts the timer at `initial_count`, it raises `vector` when the count reaches 0
+    pub fn start_timer(
+        &self,
+        vector: u8,
+        mode: TimerMode,
+        divide: TimerDivide,
+        initial_count: u32,
+    ) {
+        let mode = match mode {
+            TimerMode::OneShot => 0,
+            TimerMode::Periodic => LVT_TIMER_PERIODIC,
+        };
+
+        self.write(TIMER_DIVIDE, divide as u32);
+        self.write(LVT_TIMER, mode | u32::from(vector));
+        self.write(TIMER_INITIAL_COUNT, initial_count);
+    }
+
+    pub fn stop_timer(&self) {
+        self.write(LVT_TIMER, LVT_MASKED);
+        self.write(TIMER_INITIAL_COUNT, 0);
+    }
+
+    pub fn timer_count(&self) -> u32 {
+        self.read(TIMER_CURRENT_COUNT)
+    }
+
+    // the rate the timer counts down at with `divide`, measured against the tick counter
+    // waits for `ticks` ticks, so interrupts have to be enabled
+    pub fn measure_timer_frequency(&self, divide: TimerDivide, ticks: u64) -> u64 {
+        assert!(ticks > 0);
+
+        // counting starts at a tick boundary so that the whole ticks are measured
+        let start = crate::time::ticks() + 1;
+        while crate::time::ticks() < start {
+            x86_64::instructions::hlt();
+        }
+
+        self.write(TIMER_DIVIDE, divide as u32);
+        self.write(LVT_TIMER, LVT_MASKED);
+        self.write(TIMER_INITIAL_COUNT, u32::MAX);
+        while crate::time::ticks() < start + ticks {
+            x86_64::instructions::hlt();
+        }
+        let elapsed = u32::MAX - self.timer_count();
+        self.stop_timer();
+
+        u64::from(elapsed) * u64::from(crate::time::tick_frequency()) / ticks
+    }
+
+    fn read(&self, register: usize) -> u32 {
+        self.registers.read(register)
+    }
+
+    fn write(&self, register: usize, value: u32) {
+        self.registers.write(register, value)
+    }
+}
+
+pub fn timer_interrupts() -> u64 {
+    TIMER_INTERRUPTS.load(Ordering::Relaxed)
+}
+
+// called by the local APIC timer interrupt handler
+pub(crate) fn timer_tick() {
+    TIMER_INTERRUPTS.fetch_add(1, Ordering::Relaxed);
+}
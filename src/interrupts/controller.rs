@@ -0,0 +1,93 @@
+use conquer_once::spin::OnceCell;
+use spin::Mutex;
+use x86_64::{PhysAddr, instructions::interrupts};
+
+use crate::interrupts::apic::{self, LocalApic};
+use crate::interrupts::ioapic::{self, IoApic};
+use crate::interrupts::{InterruptIndex, PICS};
+use crate::memory::vma::VmaError;
+
+// set once the APIC took over from the 8259, which is masked from then on
+static LOCAL_APIC: OnceCell<LocalApic> = OnceCell::uninit();
+static IO_APIC: Mutex<Option<IoApic>> = Mutex::new(None);
+
+// the interrupt controller that delivers the device interrupts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Controller {
+    Pic8259,
+    Apic,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApicError {
+    Unsupported,
+    AlreadyEnabled,
+    Map(VmaError),
+}
+
+impl From<VmaError> for ApicError {
+    fn from(err: VmaError) -> Self {
+        ApicError::Map(err)
+    }
+}
+
+pub fn active() -> Controller {
+    if LOCAL_APIC.is_initialized() {
+        Controller::Apic
+    } else {
+        Controller::Pic8259
+    }
+}
+
+// the local APIC once `enable_apic` switched to it
+pub fn local_apic() -> Option<&'static LocalApic> {
+    LOCAL_APIC.try_get().ok()
+}
+
+// runs `f` with the IOAPIC, `None` while the 8259 is in charge
+pub fn with_io_apic<R>(f: impl FnOnce(&mut IoApic) -> R) -> Option<R> {
+    interrupts::without_interrupts(|| IO_APIC.lock().as_mut().map(f))
+}
+
+// tells the controller that delivered `index` that the handler is done with it, every device
+// interrupt handler calls this last
+pub fn end_of_interrupt(index: InterruptIndex) {
+    match local_apic() {
+        Some(local_apic) => local_apic.end_of_interrupt(),
+        None => unsafe { PICS.lock().notify_end_of_interrupt(index.as_u8()) },
+    }
+}
+
+// moves the device interrupts from the 8259 to the APIC: the ISA IRQs are routed through the
+// IOAPIC to the vectors they had on the 8259, so the handlers stay the same, and the 8259 is
+// masked
+// maps the registers, so kernel memory has to be initialized
+pub fn enable_apic() -> Result<(), ApicError> {
+    if !apic::is_supported() {
+        return Err(ApicError::Unsupported);
+    }
+
+    interrupts::without_interrupts(|| {
+        if LOCAL_APIC.is_initialized() {
+            return Err(ApicError::AlreadyEnabled);
+        }
+
+        let local_apic = LocalApic::map()?;
+        let mut io_apic = IoApic::map(PhysAddr::new(ioapic::DEFAULT_IOAPIC_ADDR))?;
+
+        // the 8259 goes quiet first, so no IRQ arrives through both controllers
+        unsafe { PICS.lock().disable() };
+
+        io_apic.mask_all();
+        for index in [InterruptIndex::Timer, InterruptIndex::Keyboard] {
+            let irq = index.isa_irq().expect("a device interrupt has an ISA IRQ");
+            io_apic.route(ioapic::isa_irq_to_gsi(irq), index.as_u8(), local_apic.id());
+        }
+        local_apic.enable(InterruptIndex::Spurious.as_u8());
+
+        *IO_APIC.lock() = Some(io_apic);
+        LOCAL_APIC.init_once(|| local_apic);
+
+        Ok(())
+    })
+}
@@ -0,0 +1,113 @@
+use x86_64::PhysAddr;
+
+use crate::memory::{self, mmio::MmioRegion, vma::VmaError};
+
+// where chipsets put the first IOAPIC, the MADT would have the exact address
+pub const DEFAULT_IOAPIC_ADDR: u64 = 0xfec0_0000;
+
+// the registers are reached through an index register and a data window
+const REGISTER_SELECT: usize = 0x00;
+const WINDOW: usize = 0x10;
+const REGISTERS_SIZE: usize = 0x20;
+
+const IOAPIC_ID: u32 = 0x00;
+const IOAPIC_VERSION: u32 = 0x01;
+const REDIRECTION_TABLE: u32 = 0x10;
+
+const REDIRECTION_MASKED: u64 = 1 << 16;
+
+// the legacy ISA IRQs are wired to the same global system interrupts except for the PIT,
+// which every chipset (and QEMU) routes from IRQ 0 to input 2
+// an interrupt source override in the MADT is where this is normally read from
+pub fn isa_irq_to_gsi(irq: u8) -> u32 {
+    match irq {
+        0 => 2,
+        irq => u32::from(irq),
+    }
+}
+
+// an IOAPIC, which turns device interrupt lines into messages to a local APIC
+// the registers are reached through a select and a window register, so the caller has to keep
+// two CPUs (or a CPU and an interrupt handler) from using it at the same time
+#[derive(Debug)]
+pub struct IoApic {
+    registers: MmioRegion,
+}
+
+impl IoApic {
+    pub fn map(addr: PhysAddr) -> Result<IoApic, VmaError> {
+        let registers = memory::map_mmio(addr, REGISTERS_SIZE)?;
+        Ok(IoApic { registers })
+    }
+
+    pub fn id(&mut self) -> u8 {
+        ((self.read(IOAPIC_ID) >> 24) & 0xf) as u8
+    }
+
+    // the number of interrupt inputs
+    pub fn inputs(&mut self) -> u32 {
+        ((self.read(IOAPIC_VERSION) >> 16) & 0xff) + 1
+    }
+
+    // masks every input, the firmware may have left some of them routed
+    pub fn mask_all(&mut self) {
+        for input in 0..self.inputs() {
+            self.write_entry(input, REDIRECTION_MASKED);
+        }
+    }
+
+    // delivers `input` to `vector` on the local APIC `apic_id`, edge triggered and active high
+    // as ISA interrupts are
+    pub fn route(&mut self, input: u32, vector: u8, apic_id: u8) {
+        assert!(vector >= 0x10, "vector {:#x} is reserved", vector);
+        self.write_entry(input, u64::from(apic_id) << 56 | u64::from(vector));
+    }
+
+    pub fn mask(&mut self, input: u32) {
+        let entry = self.read_entry(input);
+        self.write_entry(input, entry | REDIRECTION_MASKED);
+    }
+
+    pub fn unmask(&mut self, input: u32) {
+        let entry = self.read_entry(input);
+        self.write_entry(input, entry & !REDIRECTION_MASKED);
+    }
+
+    pub fn is_masked(&mut self, input: u32) -> bool {
+        self.read_entry(input) & REDIRECTION_MASKED != 0
+    }
+
+    fn read_entry(&mut self, input: u32) -> u64 {
+        let register = self.entry_register(input);
+        u64::from(self.read(register)) | u64::from(self.read(register + 1)) << 32
+    }
+
+    fn write_entry(&mut self, input: u32, entry: u64) {
+        let register = self.entry_register(input);
+        // the masked low half goes first, so the entry is never live half written
+        self.write(register, REDIRECTION_MASKED as u32);
+        self.write(register + 1, (entry >> 32) as u32);
+        self.write(register, entry as u32);
+    }
+
+    fn entry_register(&mut self, input: u32) -> u32 {
+        let inputs = self.inputs();
+        assert!(
+            input < inputs,
+            "the IOAPIC has {} inputs, not {}",
+            inputs,
+            input + 1
+        );
+        REDIRECTION_TABLE + input * 2
+    }
+
+    fn read(&mut self, register: u32) -> u32 {
+        self.registers.write(REGISTER_SELECT, register);
+        self.registers.read(WINDOW)
+    }
+
+    fn write(&mut self, register: u32, value: u32) {
+        self.registers.write(REGISTER_SELECT, register);
+        self.registers.write(WINDOW, value);
+    }
+}
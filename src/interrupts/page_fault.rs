@@ -0,0 +1,84 @@
+use core::fmt;
+
+use x86_64::{
+    VirtAddr,
+    structures::idt::{InterruptStackFrameValue, PageFaultErrorCode},
+};
+
+use crate::memory::{self, vma::Vma};
+
+// everything known about a page fault the kernel can't resolve, printed before it panics
+pub struct PageFaultReport<'a> {
+    pub addr: VirtAddr,
+    pub error_code: PageFaultErrorCode,
+    pub region: Option<Vma>,
+    pub stack_frame: &'a InterruptStackFrameValue,
+}
+
+impl PageFaultReport<'_> {
+    // what the faulting instruction tried to do, e.g. "kernel write to a not-present page"
+    pub fn access(&self) -> Access {
+        Access(self.error_code)
+    }
+}
+
+impl fmt::Display for PageFaultReport<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Accessed Address: {:#x}", self.addr.as_u64())?;
+        writeln!(f, "Access: {}", self.access())?;
+        match &self.region {
+            Some(vma) => writeln!(f, "Region: {}", vma)?,
+            None => writeln!(f, "Region: none")?,
+        }
+        writeln!(f, "Error Code: {:?}", self.error_code)?;
+        writeln!(f, "Page Table Walk:")?;
+        match memory::translate(self.addr) {
+            Some(translation) => write!(f, "{}", translation)?,
+            None => writeln!(f, "  physical memory isn't mapped yet")?,
+        }
+        write!(f, "{:#?}", self.stack_frame)
+    }
+}
+
+// the bits of the page fault error code in words
+pub struct Access(PageFaultErrorCode);
+
+impl fmt::Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let error_code = self.0;
+
+        let mode = if error_code.contains(PageFaultErrorCode::USER_MODE) {
+            "user"
+        } else {
+            "kernel"
+        };
+        let kind = if error_code.contains(PageFaultErrorCode::INSTRUCTION_FETCH) {
+            "instruction fetch from"
+        } else if error_code.contains(PageFaultErrorCode::CAUSED_BY_WRITE) {
+            "write to"
+        } else {
+            "read from"
+        };
+        let cause = if error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION) {
+            "a page that doesn't allow it"
+        } else {
+            "a not-present page"
+        };
+        write!(f, "{} {} {}", mode, kind, cause)?;
+
+        if error_code.contains(PageFaultErrorCode::MALFORMED_TABLE) {
+            write!(f, ", reserved bit set in a page table entry")?;
+        }
+        if error_code.contains(PageFaultErrorCode::PROTECTION_KEY) {
+            write!(f, ", denied by a protection key")?;
+        }
+        if error_code.contains(PageFaultErrorCode::SHADOW_STACK) {
+            write!(f, ", shadow stack access")?;
+        }
+        if error_code.contains(PageFaultErrorCode::SGX) {
+            write!(f, ", SGX access control")?;
+        }
+
+        Ok(())
+    }
+}
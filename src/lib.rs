@@ -2,18 +2,22 @@
 #![cfg_attr(test, no_main)]
 #![feature(custom_test_frameworks)]
 #![feature(abi_x86_interrupt)]
+#![feature(alloc_error_handler)]
 #![test_runner(crate::test_runner)]
 #![reexport_test_harness_main = "test_main"]
 
+pub mod acpi;
 pub mod allocator;
 pub mod gdt;
 pub mod interrupts;
 pub mod memory;
 pub mod serial;
 pub mod task;
+pub mod time;
 pub mod vga_buffer;
 
-use core::panic::PanicInfo;
+use allocator::HeapStats;
+use core::{fmt, panic::PanicInfo};
 extern crate alloc;
 
 #[cfg(test)]
@@ -23,6 +27,7 @@ pub fn init() {
     gdt::init();
     interrupts::init_idt();
     unsafe { interrupts::PICS.lock().initialize() };
+    time::init();
     x86_64::instructions::interrupts::enable();
 }
 
@@ -38,18 +43,84 @@ fn test_kernel_main(_boot_info: &'static BootInfo) -> ! {
 }
 
 pub trait Testable {
-    fn run(&self) -> ();
+    // returns the heap memory the test left behind
+    fn run(&self) -> Option<HeapLeak>;
 }
 
 impl<T> Testable for T
 where
     T: Fn(),
 {
-    fn run(&self) {
+    fn run(&self) -> Option<HeapLeak> {
         // this will extract the test name
         serial_print!("{} -> \t", core::any::type_name::<T>());
+        let before = allocator::heap_stats();
         self();
+        let leak = HeapLeak::between(before, allocator::heap_stats());
+
+        match leak {
+            Some(leak) => {
+                serial_println!("[leak] {}", leak);
+            }
+            None => {
+                serial_println!("[ok]");
+            }
+        }
+
+        leak
+    }
+}
+
+// opts a test that leaks on purpose out of the leak report, e.g.
+// `#[test_case] const LEAKING: AllowLeaks = AllowLeaks::new(&leaking_test);`
+// the name is taken from the type of the function, like for every other test
+pub struct AllowLeaks {
+    test: &'static dyn Fn(),
+    name: fn() -> &'static str,
+}
+
+impl AllowLeaks {
+    pub const fn new<T: Fn()>(test: &'static T) -> Self {
+        AllowLeaks {
+            test,
+            name: core::any::type_name::<T>,
+        }
+    }
+}
+
+impl Testable for AllowLeaks {
+    fn run(&self) -> Option<HeapLeak> {
+        serial_print!("{} -> \t", (self.name)());
+        (self.test)();
         serial_println!("[ok]");
+
+        None
+    }
+}
+
+// live allocations and bytes a test left on the heap
+#[derive(Debug, Clone, Copy)]
+pub struct HeapLeak {
+    pub allocations: usize,
+    pub bytes: usize,
+}
+
+impl HeapLeak {
+    fn between(before: HeapStats, after: HeapStats) -> Option<HeapLeak> {
+        if after.allocations <= before.allocations && after.allocated <= before.allocated {
+            return None;
+        }
+
+        Some(HeapLeak {
+            allocations: after.allocations.saturating_sub(before.allocations),
+            bytes: after.allocated.saturating_sub(before.allocated),
+        })
+    }
+}
+
+impl fmt::Display for HeapLeak {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} allocations, {} B", self.allocations, self.bytes)
     }
 }
 
@@ -62,8 +133,17 @@ pub fn hlt_loop() -> ! {
 
 pub fn test_runner(tests: &[&dyn Testable]) {
     serial_println!("Running {} tests", tests.len());
+    let mut leaking_tests = 0;
     for test in tests {
-        test.run();
+        if test.run().is_some() {
+            leaking_tests += 1;
+        }
+    }
+
+    // leaks are reported as `[leak]` but don't fail the run, a test that leaks on purpose is
+    // wrapped in `AllowLeaks`
+    if leaking_tests > 0 {
+        serial_println!("{} tests left memory on the heap", leaking_tests);
     }
 
     exit_qemu(QemuExitCode::Success);
@@ -13,7 +13,7 @@ use core::panic::PanicInfo;
 use p0nd_os::task::executor::Executor;
 use p0nd_os::task::keyboard::print_keypresses;
 use p0nd_os::task::task_struct::Task;
-use p0nd_os::{memory::BootInfoFrameAllocator, println};
+use p0nd_os::{memory::bitmap::BitmapFrameAllocator, println, serial_println};
 
 // type-checked way to define the function as the kernel entry point
 entry_point!(kernel_main);
@@ -27,10 +27,25 @@ fn kernel_main(boot_info: &'static BootInfo) -> ! {
     p0nd_os::init();
 
     let physical_memory_offset = VirtAddr::new(boot_info.physical_memory_offset);
-    let mut mapper = unsafe { memory::init(physical_memory_offset) };
-    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
-
-    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap init failed");
+    let mapper = unsafe { memory::init(physical_memory_offset) };
+    let frame_allocator =
+        unsafe { BitmapFrameAllocator::init(&boot_info.memory_map, physical_memory_offset) };
+
+    memory::init_kernel_memory(mapper, frame_allocator);
+    p0nd_os::gdt::init_ist_stacks();
+    if let Err(err) = p0nd_os::interrupts::enable_apic() {
+        serial_println!("staying on the 8259 PIC: {:?}", err);
+    }
+    let clock_source = p0nd_os::time::clocksource::init();
+    serial_println!("clock source: {:?}", clock_source);
+    let boot_time = p0nd_os::time::wall_clock::init();
+    println!("booted at {}", boot_time);
+    serial_println!("booted at {}", boot_time);
+    allocator::init_heap().expect("heap init failed");
+
+    let meminfo = memory::meminfo();
+    println!("{}", meminfo);
+    serial_println!("{}", meminfo);
 
     let heap_value = Box::new(1);
     println!("heap_value at {:p}", heap_value);
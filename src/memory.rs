@@ -1,21 +1,479 @@
-use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+use core::fmt;
+use core::sync::atomic::{AtomicU64, Ordering};
+
 use x86_64::{
     PhysAddr, VirtAddr,
+    instructions::tlb,
     structures::paging::{
-        FrameAllocator, Mapper, OffsetPageTable, Page, PageTable, PhysFrame, Size4KiB,
+        FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageSize, PageTable,
+        PageTableFlags, PhysFrame, Size1GiB, Size2MiB, Size4KiB, Translate,
+        mapper::{MapToError, MappedFrame, TranslateResult},
+        page_table::{PageTableEntry, PageTableLevel},
     },
 };
 
+use spin::Mutex;
+
+use crate::allocator::{self, HeapStats};
+use crate::memory::bitmap::{BitmapFrameAllocator, FrameStats};
+
+pub mod address_space;
+pub mod bitmap;
+pub mod cow;
+pub mod mmio;
+pub mod protect;
+pub mod stack;
+pub mod vma;
+pub mod walk;
+
+pub use address_space::AddressSpace;
+pub use mmio::{MmioRegion, map_mmio};
+pub use walk::{dump_mappings, translate};
+
+// the active page table and the frame allocator, shared by everything that maps memory
+// after boot (e.g. the heap when it grows)
+// the global allocator takes this lock when the heap grows, so never allocate while holding it
+pub static KERNEL_MEMORY: Mutex<Option<KernelMemory>> = Mutex::new(None);
+
+// recorded by `init` for code that can't take the `KERNEL_MEMORY` lock, e.g. exception handlers
+// the bootloader never maps physical memory at 0, so 0 means it isn't known yet
+static PHYSICAL_MEMORY_OFFSET: AtomicU64 = AtomicU64::new(0);
+
+pub struct KernelMemory {
+    pub mapper: OffsetPageTable<'static>,
+    pub frame_allocator: BitmapFrameAllocator,
+    // see `cow::zero_frame`
+    zero_frame: Option<PhysFrame>,
+}
+
+pub fn init_kernel_memory(mapper: OffsetPageTable<'static>, frame_allocator: BitmapFrameAllocator) {
+    let mut kernel_memory = KernelMemory {
+        mapper,
+        frame_allocator,
+        zero_frame: None,
+    };
+
+    // address spaces copy the kernel's level 4 entries when they are created, so the entries of
+    // the ranges the kernel maps into later have to exist from the start
+    for range_start in [
+        allocator::HEAP_START as u64,
+        stack::KERNEL_STACKS_START,
+        vma::VMA_AREA_START,
+    ] {
+        kernel_memory
+            .ensure_level_3_table(VirtAddr::new(range_start))
+            .expect("allocating a kernel page table failed");
+    }
+    protect::harden_kernel_mappings(&mut kernel_memory);
+
+    *KERNEL_MEMORY.lock() = Some(kernel_memory);
+}
+
+impl KernelMemory {
+    // maps `size` bytes from `start` on to new frames, using 1 GiB and 2 MiB pages wherever the
+    // address, the remaining size and the free frames allow it
+    // on failure the pages mapped so far are unmapped again
+    pub fn map_range(
+        &mut self,
+        start: VirtAddr,
+        size: u64,
+        flags: PageTableFlags,
+    ) -> Result<(), MapToError<Size4KiB>> {
+        assert!(start.is_aligned(Size4KiB::SIZE) && size.is_multiple_of(Size4KiB::SIZE));
+
+        let end = start + size;
+        let mut addr = start;
+        while addr < end {
+            match self.map_largest_page(addr, end - addr, flags) {
+                Ok(page_size) => addr += page_size,
+                Err(err) => {
+                    self.unmap_range(start, addr - start);
+                    return Err(err);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // maps `size` bytes from `start` on to the physical range at `phys`, e.g. device memory
+    // 1 GiB and 2 MiB pages are used where both addresses are aligned for them
+    // on failure the pages mapped so far are unmapped again
+    pub fn map_physical_range(
+        &mut self,
+        start: VirtAddr,
+        phys: PhysAddr,
+        size: u64,
+        flags: PageTableFlags,
+    ) -> Result<(), MapToError<Size4KiB>> {
+        assert!(start.is_aligned(Size4KiB::SIZE) && phys.is_aligned(Size4KiB::SIZE));
+        assert!(size.is_multiple_of(Size4KiB::SIZE));
+
+        let mut offset = 0;
+        while offset < size {
+            let (addr, frame_addr) = (start + offset, phys + offset);
+            let fits = |page_size: u64| {
+                addr.is_aligned(page_size)
+                    && frame_addr.is_aligned(page_size)
+                    && size - offset >= page_size
+            };
+
+            let result = if supports_1gib_pages() && fits(Size1GiB::SIZE) {
+                self.map_to_frame::<Size1GiB>(addr, frame_addr, flags)
+            } else if fits(Size2MiB::SIZE) {
+                self.map_to_frame::<Size2MiB>(addr, frame_addr, flags)
+            } else {
+                self.map_to_frame::<Size4KiB>(addr, frame_addr, flags)
+            };
+
+            match result {
+                Ok(page_size) => offset += page_size,
+                Err(err) => {
+                    self.unmap_physical_range(start, offset);
+                    return Err(err);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // unmaps the pages of the range and frees their frames, whatever their size
+    // pages that aren't mapped are skipped
+    pub fn unmap_range(&mut self, start: VirtAddr, size: u64) {
+        self.unmap(start, size, true);
+    }
+
+    // like `unmap_range`, but leaves the frames alone, for ranges set up by `map_physical_range`
+    pub fn unmap_physical_range(&mut self, start: VirtAddr, size: u64) {
+        self.unmap(start, size, false);
+    }
+
+    fn unmap(&mut self, start: VirtAddr, size: u64, free_frames: bool) {
+        let end = start + size;
+        let mut addr = start;
+
+        while addr < end {
+            let page_end = match self.mapper.translate(addr) {
+                TranslateResult::Mapped { frame, .. } => match frame {
+                    MappedFrame::Size4KiB(frame) => self.unmap_page(addr, end, frame, free_frames),
+                    MappedFrame::Size2MiB(frame) => self.unmap_page(addr, end, frame, free_frames),
+                    MappedFrame::Size1GiB(frame) => self.unmap_page(addr, end, frame, free_frames),
+                },
+                TranslateResult::NotMapped => addr + Size4KiB::SIZE,
+                TranslateResult::InvalidFrameAddress(phys) => {
+                    panic!("{:?} is mapped to the invalid frame {:?}", addr, phys)
+                }
+            };
+
+            addr = page_end;
+        }
+
+        self.free_empty_tables(start, end);
+    }
+
+    // frees the level 1 and level 2 tables in the range that no longer map anything, so the
+    // frames go back and a later huge page finds its entry free
+    // level 3 tables stay, every address space shares the kernel's level 4 entries
+    fn free_empty_tables(&mut self, start: VirtAddr, end: VirtAddr) {
+        let mut freed = false;
+        let mut addr = start.align_down(Size2MiB::SIZE);
+
+        while addr < end {
+            if let Some(entry) = self.entry_at(addr, PageTableLevel::Two) {
+                freed |= self.free_table_if_empty(entry);
+            }
+
+            // the level 2 table is checked once, after its last entry in the range
+            let next = addr + Size2MiB::SIZE;
+            if (next >= end || next.p3_index() != addr.p3_index())
+                && let Some(entry) = self.entry_at(addr, PageTableLevel::Three)
+            {
+                freed |= self.free_table_if_empty(entry);
+            }
+
+            addr = next;
+        }
+
+        // also drops the paging structure caches that still point to the freed tables
+        if freed {
+            tlb::flush_all();
+        }
+    }
+
+    // the entry for `addr` in the table at `level`, `None` if a table above it is missing or
+    // maps a huge page
+    fn entry_at(
+        &mut self,
+        addr: VirtAddr,
+        level: PageTableLevel,
+    ) -> Option<&'static mut PageTableEntry> {
+        let phys_offset = self.mapper.phys_offset();
+        let mut table: *mut PageTable = self.mapper.level_4_table();
+        let mut current = PageTableLevel::Four;
+
+        loop {
+            let table_ref: &'static mut PageTable = unsafe { &mut *table };
+            let entry = &mut table_ref[addr.page_table_index(current)];
+            if current == level {
+                return Some(entry);
+            }
+            if !entry.flags().contains(PageTableFlags::PRESENT)
+                || walk::maps_page(current, entry.flags())
+            {
+                return None;
+            }
+
+            table = (phys_offset + entry.addr().as_u64()).as_mut_ptr();
+            current = current.next_lower_level()?;
+        }
+    }
+
+    // frees the table the entry points to if none of its entries is used and clears the entry,
+    // the caller flushes the TLB
+    fn free_table_if_empty(&mut self, entry: &mut PageTableEntry) -> bool {
+        let flags = entry.flags();
+        if !flags.contains(PageTableFlags::PRESENT) || flags.contains(PageTableFlags::HUGE_PAGE) {
+            return false;
+        }
+
+        let frame = PhysFrame::<Size4KiB>::containing_address(entry.addr());
+        let table: *const PageTable =
+            (self.mapper.phys_offset() + frame.start_address().as_u64()).as_ptr();
+        if !unsafe { &*table }.iter().all(PageTableEntry::is_unused) {
+            return false;
+        }
+
+        entry.set_unused();
+        unsafe { self.frame_allocator.deallocate_frame(frame) };
+        true
+    }
+
+    // the frame of the kernel's level 4 table, which is active unless an address space was
+    // switched to
+    pub fn level_4_frame(&mut self) -> PhysFrame {
+        let table_addr = VirtAddr::from_ptr(self.mapper.level_4_table());
+        PhysFrame::containing_address(PhysAddr::new(table_addr - self.mapper.phys_offset()))
+    }
+
+    // creates the level 4 entry for `addr` if it is still unused
+    fn ensure_level_3_table(&mut self, addr: VirtAddr) -> Result<(), MapToError<Size4KiB>> {
+        if !self.mapper.level_4_table()[addr.p4_index()].is_unused() {
+            return Ok(());
+        }
+
+        let frame = FrameAllocator::<Size4KiB>::allocate_frame(&mut self.frame_allocator)
+            .ok_or(MapToError::FrameAllocationFailed)?;
+        let table: *mut PageTable =
+            (self.mapper.phys_offset() + frame.start_address().as_u64()).as_mut_ptr();
+        unsafe { (*table).zero() };
+
+        self.mapper.level_4_table()[addr.p4_index()]
+            .set_frame(frame, PageTableFlags::PRESENT | PageTableFlags::WRITABLE);
+        Ok(())
+    }
+
+    // maps the page to a new frame that is zeroed first, so no stale data leaks into it
+    pub fn map_zeroed_page(
+        &mut self,
+        page: Page,
+        flags: PageTableFlags,
+    ) -> Result<(), MapToError<Size4KiB>> {
+        let frame = FrameAllocator::<Size4KiB>::allocate_frame(&mut self.frame_allocator)
+            .ok_or(MapToError::FrameAllocationFailed)?;
+        let frame_ptr: *mut u8 =
+            (self.mapper.phys_offset() + frame.start_address().as_u64()).as_mut_ptr();
+        unsafe { frame_ptr.write_bytes(0, Size4KiB::SIZE as usize) };
+
+        match self.map_to_frame::<Size4KiB>(page.start_address(), frame.start_address(), flags) {
+            Ok(_) => Ok(()),
+            Err(err) => {
+                unsafe { self.frame_allocator.deallocate_frame(frame) };
+                Err(err)
+            }
+        }
+    }
+
+    // returns the size of the mapped page
+    fn map_largest_page(
+        &mut self,
+        addr: VirtAddr,
+        remaining: u64,
+        flags: PageTableFlags,
+    ) -> Result<u64, MapToError<Size4KiB>> {
+        if supports_1gib_pages() && self.try_map_huge_page::<Size1GiB>(addr, remaining, flags) {
+            return Ok(Size1GiB::SIZE);
+        }
+        if self.try_map_huge_page::<Size2MiB>(addr, remaining, flags) {
+            return Ok(Size2MiB::SIZE);
+        }
+
+        self.map_page(Page::<Size4KiB>::containing_address(addr), flags)?;
+        Ok(Size4KiB::SIZE)
+    }
+
+    // a huge page is only used if it fits completely, a huge frame is free and nothing is mapped
+    // in its range yet, otherwise the caller falls back to smaller pages
+    fn try_map_huge_page<S: PageSize>(
+        &mut self,
+        addr: VirtAddr,
+        remaining: u64,
+        flags: PageTableFlags,
+    ) -> bool
+    where
+        OffsetPageTable<'static>: Mapper<S>,
+        BitmapFrameAllocator: FrameAllocator<S> + FrameDeallocator<S>,
+    {
+        if !addr.is_aligned(S::SIZE) || remaining < S::SIZE {
+            return false;
+        }
+
+        // a table left behind under the entry by earlier small pages is in the way, it only
+        // counts as mapped while it maps something
+        let level = if S::SIZE == Size1GiB::SIZE {
+            PageTableLevel::Three
+        } else {
+            PageTableLevel::Two
+        };
+        if let Some(entry) = self.entry_at(addr, level)
+            && self.free_table_if_empty(entry)
+        {
+            tlb::flush_all();
+        }
+
+        self.map_page(Page::<S>::containing_address(addr), flags)
+            .is_ok()
+    }
+
+    fn map_page<S: PageSize>(
+        &mut self,
+        page: Page<S>,
+        flags: PageTableFlags,
+    ) -> Result<(), MapToError<S>>
+    where
+        OffsetPageTable<'static>: Mapper<S>,
+        BitmapFrameAllocator: FrameAllocator<S> + FrameDeallocator<S>,
+    {
+        let frame = FrameAllocator::<S>::allocate_frame(&mut self.frame_allocator)
+            .ok_or(MapToError::FrameAllocationFailed)?;
+
+        let result = unsafe {
+            self.mapper.map_to(
+                page,
+                frame,
+                protect::enforce_wx(flags),
+                &mut self.frame_allocator,
+            )
+        };
+        match result {
+            Ok(flush) => {
+                flush.flush();
+                Ok(())
+            }
+            Err(err) => {
+                unsafe { self.frame_allocator.deallocate_frame(frame) };
+                Err(err)
+            }
+        }
+    }
+
+    // maps the page at `addr` to the frame at `frame_addr` and returns the page size
+    fn map_to_frame<S: PageSize>(
+        &mut self,
+        addr: VirtAddr,
+        frame_addr: PhysAddr,
+        flags: PageTableFlags,
+    ) -> Result<u64, MapToError<Size4KiB>>
+    where
+        OffsetPageTable<'static>: Mapper<S>,
+    {
+        let page = Page::<S>::containing_address(addr);
+        let frame = PhysFrame::<S>::containing_address(frame_addr);
+
+        let result = unsafe {
+            self.mapper.map_to(
+                page,
+                frame,
+                protect::enforce_wx(flags),
+                &mut self.frame_allocator,
+            )
+        };
+        match result {
+            Ok(flush) => {
+                flush.flush();
+                Ok(S::SIZE)
+            }
+            Err(MapToError::FrameAllocationFailed) => Err(MapToError::FrameAllocationFailed),
+            Err(MapToError::ParentEntryHugePage) => Err(MapToError::ParentEntryHugePage),
+            Err(MapToError::PageAlreadyMapped(frame)) => Err(MapToError::PageAlreadyMapped(
+                PhysFrame::containing_address(frame.start_address()),
+            )),
+        }
+    }
+
+    // unmaps the page that maps `addr` to `frame` and returns its end
+    fn unmap_page<S: PageSize>(
+        &mut self,
+        addr: VirtAddr,
+        end: VirtAddr,
+        frame: PhysFrame<S>,
+        free_frame: bool,
+    ) -> VirtAddr
+    where
+        OffsetPageTable<'static>: Mapper<S>,
+        BitmapFrameAllocator: FrameDeallocator<S>,
+    {
+        let page = Page::<S>::containing_address(addr);
+        let page_end = page.start_address() + S::SIZE;
+        assert!(
+            page.start_address() == addr && page_end <= end,
+            "the huge page {:?} is only partly in the unmapped range",
+            page
+        );
+
+        let (_, flush) = self
+            .mapper
+            .unmap(page)
+            .expect("unmapping a mapped page failed");
+        flush.flush();
+        if free_frame {
+            unsafe { self.frame_allocator.deallocate_frame(frame) };
+        }
+
+        page_end
+    }
+}
+
+// 1 GiB pages are an optional CPU feature, reported by bit 26 of CPUID 0x8000_0001 EDX
+pub fn supports_1gib_pages() -> bool {
+    use core::arch::x86_64::__cpuid;
+
+    let max_extended_leaf = __cpuid(0x8000_0000).eax;
+    max_extended_leaf >= 0x8000_0001 && __cpuid(0x8000_0001).edx & (1 << 26) != 0
+}
+
 // unsafe because the caller must guarantee that
 // the complete physical memory is mapped to virtual one at the passed offset
 #[allow(clippy::missing_safety_doc)]
 pub unsafe fn init(physical_memory_offset: VirtAddr) -> OffsetPageTable<'static> {
+    PHYSICAL_MEMORY_OFFSET.store(physical_memory_offset.as_u64(), Ordering::Relaxed);
+
+    protect::enable();
+
     unsafe {
         let level_4_table = active_level_4_table(physical_memory_offset);
         OffsetPageTable::new(level_4_table, physical_memory_offset)
     }
 }
 
+// the offset passed to `init`, `None` before the page tables are set up
+pub fn physical_memory_offset() -> Option<VirtAddr> {
+    match PHYSICAL_MEMORY_OFFSET.load(Ordering::Relaxed) {
+        0 => None,
+        offset => Some(VirtAddr::new(offset)),
+    }
+}
+
 unsafe fn active_level_4_table(physical_memory_offset: VirtAddr) -> &'static mut PageTable {
     use x86_64::registers::control::Cr3;
 
@@ -27,64 +485,67 @@ unsafe fn active_level_4_table(physical_memory_offset: VirtAddr) -> &'static mut
     unsafe { &mut *page_table_ptr }
 }
 
-pub struct BootInfoFrameAllocator {
-    memory_map: &'static MemoryMap,
-    next: usize,
+// snapshot of the physical memory and heap usage, printed with `{}`
+pub struct MemInfo {
+    frames: FrameStats,
+    heap: HeapStats,
 }
 
-impl BootInfoFrameAllocator {
-    #[allow(clippy::missing_safety_doc)]
-    pub unsafe fn init(memory_map: &'static MemoryMap) -> Self {
-        BootInfoFrameAllocator {
-            memory_map,
-            next: 0,
-        }
-    }
-
-    fn usable_frames(&self) -> impl Iterator<Item = PhysFrame> {
-        // get all the available regions
-        let regions = self.memory_map.iter();
-        let usable_regions = regions.filter(|r| r.region_type == MemoryRegionType::Usable);
-
-        // map region to its address range
-        let address_ranges = usable_regions.map(|r| r.range.start_addr()..r.range.end_addr());
+pub fn meminfo() -> MemInfo {
+    // the heap lock is taken first and released before the kernel memory lock,
+    // a growing heap takes them in that order too
+    let heap = allocator::heap_stats();
+    let frames = KERNEL_MEMORY
+        .lock()
+        .as_ref()
+        .expect("kernel memory is not initialized")
+        .frame_allocator
+        .stats();
 
-        // transform to an iterator of frame start addresses
-        let frame_addresses = address_ranges.flat_map(|r| r.step_by(4096));
-
-        frame_addresses.map(|a| PhysFrame::containing_address(PhysAddr::new(a)))
-    }
+    MemInfo { frames, heap }
 }
 
-unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
-    fn allocate_frame(&mut self) -> Option<PhysFrame<Size4KiB>> {
-        let frame = self.usable_frames().nth(self.next);
-        self.next += 1;
-
-        frame
-    }
+// the number of physical frames the frame allocator has left, for tests that check frames are
+// given back
+#[doc(hidden)]
+pub fn free_frames() -> usize {
+    KERNEL_MEMORY
+        .lock()
+        .as_ref()
+        .expect("kernel memory is not initialized")
+        .frame_allocator
+        .free_frames()
 }
 
-pub struct EmptyFrameAllocator;
+impl fmt::Display for MemInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let frames = &self.frames;
 
-// unsafe because the allocator must yield only unused frames
-unsafe impl FrameAllocator<Size4KiB> for EmptyFrameAllocator {
-    fn allocate_frame(&mut self) -> Option<PhysFrame<Size4KiB>> {
-        None
+        writeln!(f, "physical memory:")?;
+        for (region_type, bytes) in frames.memory_regions_by_type() {
+            writeln!(f, "  {:?}: {} KiB", region_type, bytes / 1024)?;
+        }
+        writeln!(
+            f,
+            "  total {} KiB, usable {} KiB, reserved {} KiB",
+            frames.total_memory() / 1024,
+            frames.usable_memory() / 1024,
+            frames.reserved_memory() / 1024
+        )?;
+        writeln!(
+            f,
+            "  frames: {} used, {} free of {} usable",
+            frames.used_frames(),
+            frames.free_frames,
+            frames.usable_frames
+        )?;
+        write!(
+            f,
+            "heap: {} B allocated, {} B free, {} B high-water mark of {} B",
+            self.heap.allocated,
+            self.heap.free(),
+            self.heap.high_water_mark,
+            self.heap.size
+        )
     }
 }
-
-pub fn create_example_mapping(
-    page: Page,
-    mapper: &mut OffsetPageTable,
-    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
-) {
-    use x86_64::structures::paging::PageTableFlags as Flags;
-
-    let frame = PhysFrame::containing_address(PhysAddr::new(0xb8000));
-    let flags = Flags::PRESENT | Flags::WRITABLE;
-
-    let map_to_result = unsafe { mapper.map_to(page, frame, flags, frame_allocator) };
-
-    map_to_result.expect("map_to failed").flush();
-}
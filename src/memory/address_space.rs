@@ -0,0 +1,426 @@
+use core::ops::Range;
+
+use x86_64::{
+    PhysAddr, VirtAddr,
+    instructions::tlb,
+    registers::control::Cr3,
+    structures::paging::{
+        FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageSize, PageTable,
+        PageTableFlags, PhysFrame, Size1GiB, Size2MiB, Size4KiB, Translate,
+        mapper::MapToError,
+        page_table::{PageTableEntry, PageTableLevel},
+    },
+};
+
+use crate::memory::bitmap::BitmapFrameAllocator;
+use crate::memory::cow::{self, shared_flags};
+use crate::memory::walk;
+use crate::memory::{KERNEL_MEMORY, KernelMemory};
+
+// user mappings live in their own level 4 entries, every other entry is shared with the kernel
+// the kernel isn't linked into the upper half, its image, heap, stacks and the physical memory
+// mapping are spread over the lower half, so user space is a slice of it the kernel never maps
+pub const USER_SPACE_START: u64 = 0x_1000_0000_0000;
+pub const USER_SPACE_END: u64 = 0x_2000_0000_0000;
+const USER_ENTRIES: Range<usize> = 32..64;
+
+// permissions are decided by the level 1 entries, the tables above allow everything so that a
+// read-only page can become writable later
+const USER_TABLE_FLAGS: PageTableFlags = PageTableFlags::PRESENT
+    .union(PageTableFlags::WRITABLE)
+    .union(PageTableFlags::USER_ACCESSIBLE);
+
+// a level 4 table of its own that maps the kernel like every other address space, plus whatever
+// is mapped into user space
+// the user-space pages, their frames and the page tables holding them are freed on drop
+pub struct AddressSpace {
+    mapper: OffsetPageTable<'static>,
+    level_4_frame: PhysFrame,
+}
+
+impl AddressSpace {
+    // an address space with the kernel mapped and nothing in user space,
+    // `None` when there is no frame left for the level 4 table
+    pub fn new() -> Option<AddressSpace> {
+        let mut kernel_memory = KERNEL_MEMORY.lock();
+        let kernel_memory = kernel_memory
+            .as_mut()
+            .expect("kernel memory is not initialized");
+
+        let level_4_frame = zeroed_frame(kernel_memory)?;
+        let phys_offset = kernel_memory.mapper.phys_offset();
+        let level_4_table = unsafe { table_at(phys_offset, level_4_frame.start_address()) };
+
+        // the kernel's level 3 tables are shared, so later kernel mappings show up everywhere
+        for (index, entry) in kernel_memory.mapper.level_4_table().iter().enumerate() {
+            if USER_ENTRIES.contains(&index) {
+                assert!(entry.is_unused(), "the kernel has mappings in user space");
+            } else {
+                level_4_table[index] = entry.clone();
+            }
+        }
+
+        Some(AddressSpace {
+            mapper: unsafe { OffsetPageTable::new(level_4_table, phys_offset) },
+            level_4_frame,
+        })
+    }
+
+    pub fn level_4_frame(&self) -> PhysFrame {
+        self.level_4_frame
+    }
+
+    pub fn is_active(&self) -> bool {
+        Cr3::read().0 == self.level_4_frame
+    }
+
+    // loads the level 4 table into CR3, the kernel keeps running since every space maps it
+    // the address space has to stay alive until another one is activated
+    pub fn activate(&self) {
+        let (_, flags) = Cr3::read();
+        unsafe { Cr3::write(self.level_4_frame, flags) };
+    }
+
+    // maps `size` bytes from `start` in user space to new zeroed frames
+    // on failure the pages mapped so far are unmapped again
+    pub fn map_range(
+        &mut self,
+        start: VirtAddr,
+        size: u64,
+        flags: PageTableFlags,
+    ) -> Result<(), MapToError<Size4KiB>> {
+        assert_in_user_space(start, size);
+
+        let mut kernel_memory = KERNEL_MEMORY.lock();
+        let kernel_memory = kernel_memory
+            .as_mut()
+            .expect("kernel memory is not initialized");
+
+        let end = start + size;
+        let mut addr = start;
+        while addr < end {
+            if let Err(err) = self.map_page(kernel_memory, Page::containing_address(addr), flags) {
+                self.unmap_pages(kernel_memory, start, addr);
+                return Err(err);
+            }
+            addr += Size4KiB::SIZE;
+        }
+
+        Ok(())
+    }
+
+    // unmaps the user-space pages of the range and frees their frames
+    // pages that aren't mapped are skipped
+    pub fn unmap_range(&mut self, start: VirtAddr, size: u64) {
+        assert_in_user_space(start, size);
+
+        let mut kernel_memory = KERNEL_MEMORY.lock();
+        let kernel_memory = kernel_memory
+            .as_mut()
+            .expect("kernel memory is not initialized");
+        self.unmap_pages(kernel_memory, start, start + size);
+    }
+
+    // maps `size` bytes from `start` in user space to the shared zero frame, writable pages get a
+    // frame of their own on the first write to them
+    pub fn map_zero_range(
+        &mut self,
+        start: VirtAddr,
+        size: u64,
+        flags: PageTableFlags,
+    ) -> Result<(), MapToError<Size4KiB>> {
+        assert_in_user_space(start, size);
+
+        let mut kernel_memory = KERNEL_MEMORY.lock();
+        let kernel_memory = kernel_memory
+            .as_mut()
+            .expect("kernel memory is not initialized");
+        let zero_frame = cow::zero_frame(kernel_memory).ok_or(MapToError::FrameAllocationFailed)?;
+
+        let end = start + size;
+        let mut addr = start;
+        while addr < end {
+            let page = Page::containing_address(addr);
+            if let Err(err) = self.map_shared(kernel_memory, page, zero_frame, shared_flags(flags))
+            {
+                self.unmap_pages(kernel_memory, start, addr);
+                return Err(err);
+            }
+            addr += Size4KiB::SIZE;
+        }
+
+        Ok(())
+    }
+
+    // a copy of this address space that shares every user page with it, writable pages become
+    // read-only in both and are copied by the first write to them
+    // `None` when the frames for the new page tables run out
+    pub fn fork(&mut self) -> Option<AddressSpace> {
+        let mut child = AddressSpace::new()?;
+
+        let forked = {
+            let mut kernel_memory = KERNEL_MEMORY.lock();
+            let kernel_memory = kernel_memory
+                .as_mut()
+                .expect("kernel memory is not initialized");
+            let phys_offset = self.mapper.phys_offset();
+
+            let mut forked = Ok(());
+            for index in USER_ENTRIES {
+                let entry = &self.mapper.level_4_table()[index];
+                if !entry.flags().contains(PageTableFlags::PRESENT) {
+                    continue;
+                }
+
+                let table_addr = entry.addr();
+                let table_start = (index as u64) << 39;
+                let mut share_page = |page: Page, entry: &mut PageTableEntry| {
+                    if forked.is_err() {
+                        return;
+                    }
+
+                    let frame = entry.frame().expect("a present level 1 entry maps a frame");
+                    let flags = shared_flags(entry.flags());
+                    entry.set_flags(flags);
+                    forked = child.map_shared(kernel_memory, page, frame, flags);
+                };
+                unsafe {
+                    visit_pages(
+                        phys_offset,
+                        table_addr,
+                        PageTableLevel::Three,
+                        table_start,
+                        &mut share_page,
+                    )
+                };
+            }
+
+            forked
+        };
+
+        // the pages of this space that became read-only may still be writable in the TLB
+        if self.is_active() {
+            tlb::flush_all();
+        }
+
+        // dropping the child takes the kernel memory lock again
+        forked.ok().map(|_| child)
+    }
+
+    pub fn translate(&self, addr: VirtAddr) -> Option<PhysAddr> {
+        self.mapper.translate_addr(addr)
+    }
+
+    fn map_page(
+        &mut self,
+        kernel_memory: &mut KernelMemory,
+        page: Page,
+        flags: PageTableFlags,
+    ) -> Result<(), MapToError<Size4KiB>> {
+        let frame = zeroed_frame(kernel_memory).ok_or(MapToError::FrameAllocationFailed)?;
+
+        let result = unsafe {
+            self.mapper
+                .map_to(page, frame, flags, &mut kernel_memory.frame_allocator)
+        };
+        match result {
+            // only takes effect here if this space is active, the others pick it up on switch
+            Ok(flush) => {
+                flush.flush();
+                Ok(())
+            }
+            Err(err) => {
+                unsafe { kernel_memory.frame_allocator.deallocate_frame(frame) };
+                Err(err)
+            }
+        }
+    }
+
+    // maps `page` to a frame that is mapped elsewhere too and adds an owner to the frame
+    fn map_shared(
+        &mut self,
+        kernel_memory: &mut KernelMemory,
+        page: Page,
+        frame: PhysFrame,
+        flags: PageTableFlags,
+    ) -> Result<(), MapToError<Size4KiB>> {
+        let flush = unsafe {
+            self.mapper.map_to_with_table_flags(
+                page,
+                frame,
+                flags,
+                USER_TABLE_FLAGS,
+                &mut kernel_memory.frame_allocator,
+            )?
+        };
+        flush.flush();
+        kernel_memory.frame_allocator.share_frame(frame);
+
+        Ok(())
+    }
+
+    fn unmap_pages(&mut self, kernel_memory: &mut KernelMemory, start: VirtAddr, end: VirtAddr) {
+        let pages = Page::<Size4KiB>::range(
+            Page::containing_address(start),
+            Page::containing_address(end),
+        );
+        for page in pages {
+            if let Ok((frame, flush)) = self.mapper.unmap(page) {
+                flush.flush();
+                unsafe { kernel_memory.frame_allocator.deallocate_frame(frame) };
+            }
+        }
+    }
+}
+
+impl Drop for AddressSpace {
+    fn drop(&mut self) {
+        assert!(!self.is_active(), "dropping the active address space");
+
+        let mut kernel_memory = KERNEL_MEMORY.lock();
+        let kernel_memory = kernel_memory
+            .as_mut()
+            .expect("kernel memory is not initialized");
+        let phys_offset = self.mapper.phys_offset();
+
+        // the shared kernel entries are left alone
+        let level_4_table = self.mapper.level_4_table();
+        for index in USER_ENTRIES {
+            let entry = &mut level_4_table[index];
+            if entry.flags().contains(PageTableFlags::PRESENT) {
+                unsafe {
+                    free_table(
+                        &mut kernel_memory.frame_allocator,
+                        phys_offset,
+                        entry.addr(),
+                        PageTableLevel::Three,
+                    )
+                };
+            }
+            entry.set_unused();
+        }
+
+        unsafe {
+            kernel_memory
+                .frame_allocator
+                .deallocate_frame(self.level_4_frame)
+        };
+    }
+}
+
+// switches back to the kernel's own level 4 table
+pub fn activate_kernel() {
+    let level_4_frame = KERNEL_MEMORY
+        .lock()
+        .as_mut()
+        .expect("kernel memory is not initialized")
+        .level_4_frame();
+
+    let (_, flags) = Cr3::read();
+    unsafe { Cr3::write(level_4_frame, flags) };
+}
+
+fn assert_in_user_space(start: VirtAddr, size: u64) {
+    assert!(start.is_aligned(Size4KiB::SIZE) && size.is_multiple_of(Size4KiB::SIZE));
+    assert!(
+        USER_SPACE_START <= start.as_u64() && start.as_u64() + size <= USER_SPACE_END,
+        "{:?} + {:#x} is not in user space",
+        start,
+        size
+    );
+}
+
+// zeroed so that no stale kernel data ends up in user space or in a new page table
+fn zeroed_frame(kernel_memory: &mut KernelMemory) -> Option<PhysFrame> {
+    let frame = FrameAllocator::<Size4KiB>::allocate_frame(&mut kernel_memory.frame_allocator)?;
+    let phys_offset = kernel_memory.mapper.phys_offset();
+    unsafe { table_at(phys_offset, frame.start_address()).zero() };
+
+    Some(frame)
+}
+
+// calls `f` with every 4 KiB page mapped below the table at `table_addr`
+unsafe fn visit_pages(
+    phys_offset: VirtAddr,
+    table_addr: PhysAddr,
+    level: PageTableLevel,
+    table_start: u64,
+    f: &mut impl FnMut(Page, &mut PageTableEntry),
+) {
+    let shift = 12 + 9 * (level as u64 - 1);
+    let table = unsafe { table_at(phys_offset, table_addr) };
+
+    for (index, entry) in table.iter_mut().enumerate() {
+        let flags = entry.flags();
+        if !flags.contains(PageTableFlags::PRESENT) {
+            continue;
+        }
+
+        let start = table_start | (index as u64) << shift;
+        match level.next_lower_level() {
+            None => f(Page::containing_address(VirtAddr::new(start)), entry),
+            Some(next_level) if !walk::maps_page(level, flags) => unsafe {
+                visit_pages(phys_offset, entry.addr(), next_level, start, f)
+            },
+            Some(_) => panic!("huge pages in user space can't be shared"),
+        }
+    }
+}
+
+// frees the table at `table_addr`, the tables below it and the frames they map
+unsafe fn free_table(
+    frame_allocator: &mut BitmapFrameAllocator,
+    phys_offset: VirtAddr,
+    table_addr: PhysAddr,
+    level: PageTableLevel,
+) {
+    let table = unsafe { table_at(phys_offset, table_addr) };
+    for entry in table.iter() {
+        let flags = entry.flags();
+        if !flags.contains(PageTableFlags::PRESENT) {
+            continue;
+        }
+
+        match level.next_lower_level() {
+            Some(next_level) if !walk::maps_page(level, flags) => unsafe {
+                free_table(frame_allocator, phys_offset, entry.addr(), next_level)
+            },
+            _ => unsafe { free_frame(frame_allocator, entry.addr(), level) },
+        }
+    }
+
+    unsafe {
+        frame_allocator.deallocate_frame(PhysFrame::<Size4KiB>::containing_address(table_addr))
+    };
+}
+
+unsafe fn free_frame(
+    frame_allocator: &mut BitmapFrameAllocator,
+    addr: PhysAddr,
+    level: PageTableLevel,
+) {
+    match level {
+        PageTableLevel::Three => unsafe {
+            FrameDeallocator::<Size1GiB>::deallocate_frame(
+                frame_allocator,
+                PhysFrame::containing_address(addr),
+            )
+        },
+        PageTableLevel::Two => unsafe {
+            FrameDeallocator::<Size2MiB>::deallocate_frame(
+                frame_allocator,
+                PhysFrame::containing_address(addr),
+            )
+        },
+        _ => unsafe {
+            FrameDeallocator::<Size4KiB>::deallocate_frame(
+                frame_allocator,
+                PhysFrame::containing_address(addr),
+            )
+        },
+    }
+}
+
+unsafe fn table_at(phys_offset: VirtAddr, table_addr: PhysAddr) -> &'static mut PageTable {
+    unsafe { &mut *(phys_offset + table_addr.as_u64()).as_mut_ptr() }
+}
@@ -0,0 +1,343 @@
+use core::slice;
+
+use bootloader::bootinfo::{MemoryMap, MemoryRegion, MemoryRegionType};
+use x86_64::{
+    PhysAddr, VirtAddr,
+    structures::paging::{
+        FrameAllocator, FrameDeallocator, PageSize, PhysFrame, Size1GiB, Size2MiB, Size4KiB,
+        frame::PhysFrameRange,
+    },
+};
+
+const FRAME_SIZE: u64 = 4096;
+const BITS_PER_WORD: usize = u64::BITS as usize;
+
+// physical frame allocator backed by a two level bitmap
+// `bitmap` holds one bit per frame (set == free) and `summary` holds one bit per bitmap word
+// (set == the word has at least one free frame), so finding a free frame only scans
+// the summary, which is 4096 times smaller than the frame count
+// frames mapped in several places (copy-on-write, the shared zero page) carry a count of their
+// extra owners, deallocating such a frame only drops one owner
+pub struct BitmapFrameAllocator {
+    memory_map: &'static MemoryMap,
+    bitmap: &'static mut [u64],
+    summary: &'static mut [u64],
+    // owners besides the first one, per frame
+    shares: &'static mut [u16],
+    usable_frames: usize,
+    free_frames: usize,
+    // lowest summary word that may still have a free frame
+    next_summary: usize,
+}
+
+impl BitmapFrameAllocator {
+    // unsafe because the caller must guarantee that the memory map is valid
+    // and that the complete physical memory is mapped at the passed offset
+    #[allow(clippy::missing_safety_doc)]
+    pub unsafe fn init(memory_map: &'static MemoryMap, physical_memory_offset: VirtAddr) -> Self {
+        let usable_regions = || {
+            memory_map
+                .iter()
+                .filter(|r| r.region_type == MemoryRegionType::Usable)
+        };
+
+        // the bitmap only has to cover frames up to the end of the highest usable region
+        let frame_count = usable_regions()
+            .map(|r| r.range.end_frame_number)
+            .max()
+            .unwrap_or(0) as usize;
+        let bitmap_words = frame_count.div_ceil(BITS_PER_WORD);
+        let summary_words = bitmap_words.div_ceil(BITS_PER_WORD);
+        let share_counts = bitmap_words * BITS_PER_WORD;
+        let metadata_bytes = ((bitmap_words + summary_words) * 8 + share_counts * 2) as u64;
+        let metadata_frames = metadata_bytes.div_ceil(FRAME_SIZE);
+
+        // the bitmap itself lives in the first usable region that is large enough to hold it
+        let metadata_region = usable_regions()
+            .find(|r| r.range.end_frame_number - r.range.start_frame_number >= metadata_frames)
+            .expect("no usable region is large enough for the frame bitmap");
+        let metadata_start = physical_memory_offset + metadata_region.range.start_addr();
+        let metadata_ptr: *mut u64 = metadata_start.as_mut_ptr();
+
+        let (bitmap, summary, shares) = unsafe {
+            (
+                slice::from_raw_parts_mut(metadata_ptr, bitmap_words),
+                slice::from_raw_parts_mut(metadata_ptr.add(bitmap_words), summary_words),
+                slice::from_raw_parts_mut(
+                    metadata_ptr.add(bitmap_words + summary_words).cast::<u16>(),
+                    share_counts,
+                ),
+            )
+        };
+        bitmap.fill(0);
+        summary.fill(0);
+        shares.fill(0);
+
+        let mut allocator = BitmapFrameAllocator {
+            memory_map,
+            bitmap,
+            summary,
+            shares,
+            usable_frames: 0,
+            free_frames: 0,
+            next_summary: 0,
+        };
+
+        for region in usable_regions() {
+            for index in region.range.start_frame_number..region.range.end_frame_number {
+                allocator.mark_free(index as usize);
+            }
+        }
+        allocator.usable_frames = allocator.free_frames;
+
+        let metadata_first = metadata_region.range.start_frame_number;
+        for index in metadata_first..metadata_first + metadata_frames {
+            allocator.mark_used(index as usize);
+        }
+
+        allocator
+    }
+
+    pub fn free_frames(&self) -> usize {
+        self.free_frames
+    }
+
+    pub fn stats(&self) -> FrameStats {
+        FrameStats {
+            memory_map: self.memory_map,
+            usable_frames: self.usable_frames,
+            free_frames: self.free_frames,
+        }
+    }
+
+    pub fn is_free(&self, frame: PhysFrame) -> bool {
+        let index = frame_index(frame);
+        index < self.bitmap.len() * BITS_PER_WORD && self.is_index_free(index)
+    }
+
+    // adds an owner to an allocated frame, it is only freed once every owner deallocated it
+    pub fn share_frame(&mut self, frame: PhysFrame) {
+        let index = frame_index(frame);
+        assert!(
+            index < self.shares.len() && !self.is_index_free(index),
+            "sharing the unallocated frame {:?}",
+            frame
+        );
+
+        self.shares[index] = self.shares[index]
+            .checked_add(1)
+            .expect("frame shared too often");
+    }
+
+    // the number of owners of the frame, 0 if it is free
+    pub fn ref_count(&self, frame: PhysFrame) -> usize {
+        let index = frame_index(frame);
+        if index >= self.shares.len() || self.is_index_free(index) {
+            return 0;
+        }
+
+        self.shares[index] as usize + 1
+    }
+
+    // finds `count` physically contiguous free frames whose first frame is aligned
+    // to `align` frames (a power of two), e.g. for DMA buffers
+    pub fn allocate_contiguous(&mut self, count: usize, align: usize) -> Option<PhysFrameRange> {
+        assert!(count > 0, "cannot allocate an empty frame range");
+        assert!(
+            align.is_power_of_two(),
+            "frame alignment must be a power of two"
+        );
+
+        let frame_limit = self.bitmap.len() * BITS_PER_WORD;
+        let mut start = self.next_summary * BITS_PER_WORD * BITS_PER_WORD;
+        start = align_up(start, align);
+
+        while start + count <= frame_limit {
+            match (start..start + count).find(|&index| !self.is_index_free(index)) {
+                // skip past the used frame, there is no free run that contains it
+                Some(used) => start = align_up(used + 1, align),
+                None => {
+                    for index in start..start + count {
+                        self.mark_used(index);
+                    }
+
+                    let first = index_frame(start);
+                    return Some(PhysFrame::range(first, first + count as u64));
+                }
+            }
+        }
+
+        None
+    }
+
+    // unsafe because the caller must guarantee that no frame of the range is still in use
+    #[allow(clippy::missing_safety_doc)]
+    pub unsafe fn deallocate_contiguous(&mut self, range: PhysFrameRange) {
+        for frame in range {
+            unsafe { self.deallocate_frame(frame) };
+        }
+    }
+
+    // huge frames are contiguous runs of 4 KiB frames aligned to their size
+    fn allocate_huge_frame<S: PageSize>(&mut self) -> Option<PhysFrame<S>> {
+        let frames = (S::SIZE / FRAME_SIZE) as usize;
+        let range = self.allocate_contiguous(frames, frames)?;
+
+        Some(PhysFrame::from_start_address(range.start.start_address()).unwrap())
+    }
+
+    unsafe fn deallocate_huge_frame<S: PageSize>(&mut self, frame: PhysFrame<S>) {
+        let first = PhysFrame::containing_address(frame.start_address());
+        unsafe {
+            self.deallocate_contiguous(PhysFrame::range(first, first + S::SIZE / FRAME_SIZE))
+        };
+    }
+
+    fn is_index_free(&self, index: usize) -> bool {
+        self.bitmap[index / BITS_PER_WORD] & (1 << (index % BITS_PER_WORD)) != 0
+    }
+
+    fn mark_free(&mut self, index: usize) {
+        let word = index / BITS_PER_WORD;
+        let summary = word / BITS_PER_WORD;
+
+        self.bitmap[word] |= 1 << (index % BITS_PER_WORD);
+        self.summary[summary] |= 1 << (word % BITS_PER_WORD);
+        self.free_frames += 1;
+        self.next_summary = self.next_summary.min(summary);
+    }
+
+    fn mark_used(&mut self, index: usize) {
+        let word = index / BITS_PER_WORD;
+
+        self.bitmap[word] &= !(1 << (index % BITS_PER_WORD));
+        if self.bitmap[word] == 0 {
+            self.summary[word / BITS_PER_WORD] &= !(1 << (word % BITS_PER_WORD));
+        }
+        self.free_frames -= 1;
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct FrameStats {
+    memory_map: &'static MemoryMap,
+    pub usable_frames: usize,
+    pub free_frames: usize,
+}
+
+impl FrameStats {
+    // includes the frames that hold the bitmap itself
+    pub fn used_frames(&self) -> usize {
+        self.usable_frames - self.free_frames
+    }
+
+    pub fn total_memory(&self) -> u64 {
+        self.memory_map.iter().map(region_size).sum()
+    }
+
+    pub fn usable_memory(&self) -> u64 {
+        self.memory_by_type(MemoryRegionType::Usable)
+    }
+
+    pub fn reserved_memory(&self) -> u64 {
+        self.total_memory() - self.usable_memory()
+    }
+
+    pub fn memory_by_type(&self, region_type: MemoryRegionType) -> u64 {
+        self.memory_map
+            .iter()
+            .filter(|r| r.region_type == region_type)
+            .map(region_size)
+            .sum()
+    }
+
+    // yields every region type of the memory map once, together with its total size
+    pub fn memory_regions_by_type(&self) -> impl Iterator<Item = (MemoryRegionType, u64)> + '_ {
+        self.memory_map
+            .iter()
+            .enumerate()
+            .filter(|(i, r)| {
+                !self.memory_map[..*i]
+                    .iter()
+                    .any(|earlier| earlier.region_type == r.region_type)
+            })
+            .map(|(_, r)| (r.region_type, self.memory_by_type(r.region_type)))
+    }
+}
+
+unsafe impl FrameAllocator<Size4KiB> for BitmapFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame<Size4KiB>> {
+        while let Some(&summary_word) = self.summary.get(self.next_summary) {
+            if summary_word == 0 {
+                self.next_summary += 1;
+                continue;
+            }
+
+            let word = self.next_summary * BITS_PER_WORD + summary_word.trailing_zeros() as usize;
+            let index = word * BITS_PER_WORD + self.bitmap[word].trailing_zeros() as usize;
+            self.mark_used(index);
+
+            return Some(index_frame(index));
+        }
+
+        None
+    }
+}
+
+impl FrameDeallocator<Size4KiB> for BitmapFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame<Size4KiB>) {
+        let index = frame_index(frame);
+        assert!(
+            index < self.bitmap.len() * BITS_PER_WORD,
+            "frame {:?} is outside of the managed memory",
+            frame
+        );
+        assert!(!self.is_index_free(index), "frame {:?} freed twice", frame);
+
+        if self.shares[index] > 0 {
+            self.shares[index] -= 1;
+            return;
+        }
+        self.mark_free(index);
+    }
+}
+
+unsafe impl FrameAllocator<Size2MiB> for BitmapFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame<Size2MiB>> {
+        self.allocate_huge_frame()
+    }
+}
+
+impl FrameDeallocator<Size2MiB> for BitmapFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame<Size2MiB>) {
+        unsafe { self.deallocate_huge_frame(frame) }
+    }
+}
+
+unsafe impl FrameAllocator<Size1GiB> for BitmapFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame<Size1GiB>> {
+        self.allocate_huge_frame()
+    }
+}
+
+impl FrameDeallocator<Size1GiB> for BitmapFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame<Size1GiB>) {
+        unsafe { self.deallocate_huge_frame(frame) }
+    }
+}
+
+fn region_size(region: &MemoryRegion) -> u64 {
+    region.range.end_addr() - region.range.start_addr()
+}
+
+fn frame_index(frame: PhysFrame) -> usize {
+    (frame.start_address().as_u64() / FRAME_SIZE) as usize
+}
+
+fn index_frame(index: usize) -> PhysFrame {
+    PhysFrame::containing_address(PhysAddr::new(index as u64 * FRAME_SIZE))
+}
+
+fn align_up(value: usize, align: usize) -> usize {
+    (value + align - 1) & !(align - 1)
+}
@@ -0,0 +1,120 @@
+use x86_64::{
+    VirtAddr,
+    instructions::tlb,
+    registers::control::Cr3,
+    structures::paging::{
+        FrameAllocator, FrameDeallocator, PageSize, PageTable, PageTableFlags, PhysFrame, Size4KiB,
+        page_table::{PageTableEntry, PageTableLevel},
+    },
+};
+
+use crate::memory::walk;
+use crate::memory::{KERNEL_MEMORY, KernelMemory};
+
+// marks a read-only page whose frame is shared copy-on-write, one of the bits the CPU ignores
+pub const COPY_ON_WRITE: PageTableFlags = PageTableFlags::BIT_9;
+
+// the flags a page that shares its frame gets instead of `flags`, writable pages become read-only
+// until the first write to them gives them a frame of their own
+pub fn shared_flags(flags: PageTableFlags) -> PageTableFlags {
+    if flags.contains(PageTableFlags::WRITABLE) {
+        flags
+            .difference(PageTableFlags::WRITABLE)
+            .union(COPY_ON_WRITE)
+    } else {
+        flags
+    }
+}
+
+// a zeroed frame that backs every page mapped with `AddressSpace::map_zero_range` until it is
+// written to, allocated on first use
+// kernel memory holds a reference of its own, so unmapping the pages never frees it
+pub(crate) fn zero_frame(kernel_memory: &mut KernelMemory) -> Option<PhysFrame> {
+    if let Some(frame) = kernel_memory.zero_frame {
+        return Some(frame);
+    }
+
+    let frame = FrameAllocator::<Size4KiB>::allocate_frame(&mut kernel_memory.frame_allocator)?;
+    let frame_ptr: *mut u8 =
+        (kernel_memory.mapper.phys_offset() + frame.start_address().as_u64()).as_mut_ptr();
+    unsafe { frame_ptr.write_bytes(0, Size4KiB::SIZE as usize) };
+    kernel_memory.zero_frame = Some(frame);
+
+    Some(frame)
+}
+
+// gives the copy-on-write page at `addr` a frame of its own and makes it writable, called by the
+// page fault handler for writes to present pages
+// the last owner of a frame keeps it, the others get a copy
+// the faulting code may hold the kernel memory lock, so this gives up instead of spinning on it
+pub fn handle_cow_fault(addr: VirtAddr) -> bool {
+    let Some(mut kernel_memory) = KERNEL_MEMORY.try_lock() else {
+        return false;
+    };
+    let Some(kernel_memory) = kernel_memory.as_mut() else {
+        return false;
+    };
+
+    let phys_offset = kernel_memory.mapper.phys_offset();
+    let Some(entry) = (unsafe { active_level_1_entry(phys_offset, addr) }) else {
+        return false;
+    };
+    let flags = entry.flags();
+    if !flags.contains(PageTableFlags::PRESENT | COPY_ON_WRITE) {
+        return false;
+    }
+
+    let frame = entry.frame().expect("a present level 1 entry maps a frame");
+    let writable_flags = flags
+        .difference(COPY_ON_WRITE)
+        .union(PageTableFlags::WRITABLE);
+
+    if kernel_memory.frame_allocator.ref_count(frame) > 1 {
+        let Some(copy) =
+            FrameAllocator::<Size4KiB>::allocate_frame(&mut kernel_memory.frame_allocator)
+        else {
+            return false;
+        };
+        unsafe {
+            let from: *const u8 = (phys_offset + frame.start_address().as_u64()).as_ptr();
+            let to: *mut u8 = (phys_offset + copy.start_address().as_u64()).as_mut_ptr();
+            to.copy_from_nonoverlapping(from, Size4KiB::SIZE as usize);
+        }
+
+        entry.set_frame(copy, writable_flags);
+        // drops this page's reference, the other owners keep the frame
+        unsafe { kernel_memory.frame_allocator.deallocate_frame(frame) };
+    } else {
+        entry.set_flags(writable_flags);
+    }
+    tlb::flush(addr);
+
+    true
+}
+
+// the level 1 entry that maps `addr` in the active page tables,
+// `None` if a level above isn't present or maps a huge page
+unsafe fn active_level_1_entry(
+    phys_offset: VirtAddr,
+    addr: VirtAddr,
+) -> Option<&'static mut PageTableEntry> {
+    let mut table_addr = Cr3::read().0.start_address();
+    let mut level = PageTableLevel::Four;
+
+    loop {
+        let table: &'static mut PageTable =
+            unsafe { &mut *(phys_offset + table_addr.as_u64()).as_mut_ptr() };
+        let entry = &mut table[addr.page_table_index(level)];
+        let next_level = level.next_lower_level();
+
+        match next_level {
+            None => return Some(entry),
+            Some(_) if !entry.flags().contains(PageTableFlags::PRESENT) => return None,
+            Some(_) if walk::maps_page(level, entry.flags()) => return None,
+            Some(next_level) => {
+                table_addr = entry.addr();
+                level = next_level;
+            }
+        }
+    }
+}
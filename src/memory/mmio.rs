@@ -0,0 +1,114 @@
+use core::{mem, ptr};
+
+use x86_64::{
+    PhysAddr, VirtAddr,
+    structures::paging::{PageSize, PageTableFlags, PhysFrame, Size4KiB},
+};
+
+use crate::memory::KERNEL_MEMORY;
+use crate::memory::vma::{self, VmaBacking, VmaError};
+
+// the physical memory offset mapping is cacheable, device registers must bypass the caches
+// PCD and PWT together select the uncacheable memory type with the default PAT
+const MMIO_FLAGS: PageTableFlags = PageTableFlags::PRESENT
+    .union(PageTableFlags::WRITABLE)
+    .union(PageTableFlags::NO_CACHE)
+    .union(PageTableFlags::WRITE_THROUGH)
+    .union(PageTableFlags::NO_EXECUTE);
+
+// uncached mapping of a physical MMIO range, unmapped on drop
+// all accesses are volatile and checked against the bounds of the range
+#[derive(Debug)]
+pub struct MmioRegion {
+    phys: PhysAddr,
+    virt: VirtAddr,
+    size: usize,
+    // the page aligned VMA the range lies in
+    vma_start: VirtAddr,
+    vma_size: u64,
+}
+
+// maps `len` bytes of device memory at `phys` into the VMA area
+pub fn map_mmio(phys: PhysAddr, len: usize) -> Result<MmioRegion, VmaError> {
+    assert!(len > 0, "MMIO range is empty");
+
+    let first_frame: PhysFrame<Size4KiB> = PhysFrame::containing_address(phys);
+    let last_frame: PhysFrame<Size4KiB> = PhysFrame::containing_address(phys + (len - 1) as u64);
+    let size = (last_frame - first_frame + 1) * Size4KiB::SIZE;
+    let frame_offset = phys - first_frame.start_address();
+
+    // the frames belong to the device, so they are never handed to the frame allocator
+    let vma = vma::reserve("mmio", size, MMIO_FLAGS, VmaBacking::External)?;
+
+    let mapped = KERNEL_MEMORY
+        .lock()
+        .as_mut()
+        .expect("kernel memory is not initialized")
+        .map_physical_range(vma.start, first_frame.start_address(), size, MMIO_FLAGS);
+
+    if let Err(err) = mapped {
+        vma::release(vma.start).expect("MMIO region is missing");
+        return Err(VmaError::from(err));
+    }
+
+    Ok(MmioRegion {
+        phys,
+        virt: vma.start + frame_offset,
+        size: len,
+        vma_start: vma.start,
+        vma_size: size,
+    })
+}
+
+impl MmioRegion {
+    pub fn phys_addr(&self) -> PhysAddr {
+        self.phys
+    }
+
+    pub fn virt_addr(&self) -> VirtAddr {
+        self.virt
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    // volatile read of the `T` at `offset` bytes into the region
+    pub fn read<T: Copy>(&self, offset: usize) -> T {
+        unsafe { ptr::read_volatile(self.ptr(offset)) }
+    }
+
+    // volatile write of the `T` at `offset` bytes into the region
+    pub fn write<T: Copy>(&self, offset: usize, value: T) {
+        unsafe { ptr::write_volatile(self.ptr(offset), value) }
+    }
+
+    fn ptr<T>(&self, offset: usize) -> *mut T {
+        assert!(
+            offset + mem::size_of::<T>() <= self.size,
+            "MMIO access at {:#x} is out of bounds of {:#x} bytes",
+            offset,
+            self.size
+        );
+        let ptr = (self.virt + offset as u64).as_mut_ptr::<T>();
+        assert!(
+            ptr.is_aligned(),
+            "MMIO access at {:#x} is unaligned",
+            offset
+        );
+
+        ptr
+    }
+}
+
+impl Drop for MmioRegion {
+    fn drop(&mut self) {
+        KERNEL_MEMORY
+            .lock()
+            .as_mut()
+            .expect("kernel memory is not initialized")
+            .unmap_physical_range(self.vma_start, self.vma_size);
+
+        vma::release(self.vma_start).expect("MMIO region is missing");
+    }
+}
@@ -0,0 +1,216 @@
+use core::ptr;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use x86_64::{
+    PhysAddr, VirtAddr,
+    instructions::tlb,
+    registers::{
+        control::{Cr0, Cr0Flags},
+        model_specific::{Efer, EferFlags},
+    },
+    structures::paging::{
+        Mapper, OffsetPageTable, Page, PageTable, PageTableFlags, Size4KiB, Translate,
+        page_table::PageTableLevel,
+    },
+};
+
+use crate::memory::KernelMemory;
+use crate::memory::walk;
+use crate::serial_println;
+
+const PT_LOAD: u32 = 1;
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+const ELF_HEADER_SIZE: usize = 0x40;
+
+// set once the kernel image was remapped from its program headers
+static KERNEL_IMAGE_HARDENED: AtomicBool = AtomicBool::new(false);
+
+unsafe extern "C" {
+    // defined by the linker at the ELF header, which is loaded with the first kernel segment
+    static __ehdr_start: u8;
+}
+
+// the flags every kernel mapping is made with, a writable page is never executable
+pub fn enforce_wx(flags: PageTableFlags) -> PageTableFlags {
+    if flags.contains(PageTableFlags::WRITABLE) {
+        flags | PageTableFlags::NO_EXECUTE
+    } else {
+        flags
+    }
+}
+
+// turns on the CPU features the protection relies on, has to run before any page is mapped with
+// `NO_EXECUTE`, since the bit is reserved while EFER.NXE is off
+// CR0.WP makes the kernel fault on writes to read-only pages as well, which copy-on-write needs
+pub fn enable() {
+    unsafe {
+        Efer::update(|efer| efer.insert(EferFlags::NO_EXECUTE_ENABLE));
+        Cr0::update(|cr0| cr0.insert(Cr0Flags::WRITE_PROTECT));
+    }
+}
+
+// remaps the kernel image so that code is read-only and executable and everything else is
+// not executable, then marks every other writable kernel mapping (stacks, the physical memory
+// mapping, the VGA buffer, ...) as not executable
+// if the linker didn't leave the ELF header mapped, the kernel image keeps the flags the
+// bootloader mapped it with
+pub fn harden_kernel_mappings(kernel_memory: &mut KernelMemory) {
+    let Some(segments) = kernel_segments(&kernel_memory.mapper) else {
+        serial_println!(
+            "WARNING: the kernel ELF header isn't mapped, the kernel image isn't remapped"
+        );
+        return forbid_all_writable_code(kernel_memory);
+    };
+
+    for segment in segments {
+        let flags = segment.page_flags();
+        let pages = Page::<Size4KiB>::range_inclusive(
+            Page::containing_address(segment.start),
+            Page::containing_address(segment.end - 1u64),
+        );
+
+        for page in pages {
+            let result = unsafe { kernel_memory.mapper.update_flags(page, flags) };
+            result
+                .expect("the kernel image isn't mapped with 4 KiB pages")
+                .ignore();
+        }
+    }
+
+    forbid_all_writable_code(kernel_memory);
+    KERNEL_IMAGE_HARDENED.store(true, Ordering::Relaxed);
+}
+
+// whether `harden_kernel_mappings` found the ELF header and remapped the kernel image, the
+// bootloader already maps code read-only, so that alone doesn't tell
+pub fn kernel_image_hardened() -> bool {
+    KERNEL_IMAGE_HARDENED.load(Ordering::Relaxed)
+}
+
+fn forbid_all_writable_code(kernel_memory: &mut KernelMemory) {
+    let phys_offset = kernel_memory.mapper.phys_offset();
+    let level_4_table = kernel_memory.mapper.level_4_table();
+    unsafe { forbid_writable_code(phys_offset, level_4_table, PageTableLevel::Four) };
+
+    tlb::flush_all();
+}
+
+// a loadable segment of the kernel ELF image, as linked
+struct KernelSegment {
+    start: VirtAddr,
+    end: VirtAddr,
+    flags: u32,
+}
+
+impl KernelSegment {
+    fn page_flags(&self) -> PageTableFlags {
+        let writable = self.flags & PF_W != 0;
+        let executable = self.flags & PF_X != 0;
+        assert!(
+            !(writable && executable),
+            "kernel segment {:?}-{:?} is writable and executable",
+            self.start,
+            self.end
+        );
+
+        let mut flags = PageTableFlags::PRESENT;
+        if writable {
+            flags |= PageTableFlags::WRITABLE;
+        }
+        if !executable {
+            flags |= PageTableFlags::NO_EXECUTE;
+        }
+
+        flags
+    }
+}
+
+// reads the program headers of the running kernel through the ELF header the linker left mapped
+// `None` if the header or the program headers aren't mapped
+fn kernel_segments(
+    mapper: &OffsetPageTable,
+) -> Option<impl Iterator<Item = KernelSegment> + use<>> {
+    let mapped = |offset: usize, len: usize| {
+        let start = elf_address(offset);
+        Page::<Size4KiB>::range_inclusive(
+            Page::containing_address(VirtAddr::new(start as u64)),
+            Page::containing_address(VirtAddr::new((start + len - 1) as u64)),
+        )
+        .all(|page| mapper.translate_addr(page.start_address()).is_some())
+    };
+
+    if !mapped(0, ELF_HEADER_SIZE) || unsafe { elf_field::<[u8; 4]>(0) } != *b"\x7fELF" {
+        return None;
+    }
+    let program_headers = unsafe { elf_field::<u64>(0x20) } as usize;
+    let entry_size = unsafe { elf_field::<u16>(0x36) } as usize;
+    let entries = unsafe { elf_field::<u16>(0x38) } as usize;
+    if entry_size < 0x30 || (entries > 0 && !mapped(program_headers, entries * entry_size)) {
+        return None;
+    }
+
+    Some((0..entries).filter_map(move |index| {
+        let header = program_headers + index * entry_size;
+        let (segment_type, flags, start, size) = unsafe {
+            (
+                elf_field::<u32>(header),
+                elf_field::<u32>(header + 0x04),
+                elf_field::<u64>(header + 0x10),
+                elf_field::<u64>(header + 0x28),
+            )
+        };
+
+        (segment_type == PT_LOAD && size > 0).then(|| KernelSegment {
+            start: VirtAddr::new(start),
+            end: VirtAddr::new(start + size),
+            flags,
+        })
+    }))
+}
+
+// the field at `offset` bytes into the kernel ELF file
+// the address is computed rather than offset from `__ehdr_start`, which the compiler only knows
+// as a single byte
+unsafe fn elf_field<T: Copy>(offset: usize) -> T {
+    let field = ptr::with_exposed_provenance::<T>(elf_address(offset));
+    unsafe { ptr::read_unaligned(field) }
+}
+
+fn elf_address(offset: usize) -> usize {
+    (&raw const __ehdr_start).expose_provenance() + offset
+}
+
+// sets `NO_EXECUTE` on every writable page the table maps
+// the bit is only set on the entries that map pages, on a table entry it would cover every page
+// below it
+unsafe fn forbid_writable_code(
+    phys_offset: VirtAddr,
+    table: &mut PageTable,
+    level: PageTableLevel,
+) {
+    for entry in table.iter_mut() {
+        let flags = entry.flags();
+        if !flags.contains(PageTableFlags::PRESENT) {
+            continue;
+        }
+
+        match level.next_lower_level() {
+            Some(next_level) if !walk::maps_page(level, flags) => unsafe {
+                forbid_writable_code(phys_offset, table_at(phys_offset, entry.addr()), next_level)
+            },
+            _ => entry.set_flags(enforce_wx(flags)),
+        }
+    }
+}
+
+unsafe fn table_at(phys_offset: VirtAddr, table_addr: PhysAddr) -> &'static mut PageTable {
+    unsafe { &mut *(phys_offset + table_addr.as_u64()).as_mut_ptr() }
+}
+
+#[test_case]
+fn test_writable_pages_are_not_executable() {
+    let data = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    assert!(enforce_wx(data).contains(PageTableFlags::NO_EXECUTE));
+    assert_eq!(enforce_wx(PageTableFlags::PRESENT), PageTableFlags::PRESENT);
+}
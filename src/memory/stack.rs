@@ -0,0 +1,132 @@
+use spin::Mutex;
+use x86_64::{
+    VirtAddr,
+    structures::paging::{Page, PageTableFlags},
+};
+
+use crate::memory::KERNEL_MEMORY;
+use crate::memory::vma::{self, VmaBacking};
+
+// kernel stacks live in their own virtual range, one fixed size slot per stack
+// a stack takes the top of its slot and the pages below it stay unmapped, so running off the
+// end of a stack hits a guard page instead of corrupting whatever lies below
+pub const KERNEL_STACKS_START: u64 = 0x_5555_0000_0000;
+const STACK_SLOT_PAGES: u64 = 16;
+// the lowest page of every slot is always a guard page
+pub const MAX_STACK_PAGES: usize = STACK_SLOT_PAGES as usize - 1;
+const MAX_KERNEL_STACKS: usize = 64;
+const STACK_FLAGS: PageTableFlags = PageTableFlags::PRESENT
+    .union(PageTableFlags::WRITABLE)
+    .union(PageTableFlags::NO_EXECUTE);
+
+// one bit per slot, set while the slot holds a stack
+// lock order: STACK_SLOTS before the VMAs
+static STACK_SLOTS: Mutex<StackSlots> = Mutex::new(StackSlots {
+    used: 0,
+    reserved: false,
+});
+
+struct StackSlots {
+    used: u64,
+    // the whole stack range is reserved as a single region with the first stack, so stacks don't
+    // take up the slots of the VMA table
+    reserved: bool,
+}
+
+// a mapped kernel stack, the pages are unmapped and their frames freed on drop
+#[derive(Debug)]
+pub struct KernelStack {
+    slot: usize,
+    bottom: Page,
+    pages: usize,
+}
+
+impl KernelStack {
+    // the initial stack pointer, stacks grow down from here
+    pub fn top(&self) -> VirtAddr {
+        (self.bottom + self.pages as u64).start_address()
+    }
+
+    pub fn bottom(&self) -> VirtAddr {
+        self.bottom.start_address()
+    }
+
+    pub fn guard_page(&self) -> Page {
+        self.bottom - 1
+    }
+
+    pub fn pages(&self) -> usize {
+        self.pages
+    }
+}
+
+impl Drop for KernelStack {
+    fn drop(&mut self) {
+        KERNEL_MEMORY
+            .lock()
+            .as_mut()
+            .expect("kernel memory is not initialized")
+            .unmap_range(self.bottom(), self.pages as u64 * 4096);
+
+        release_slot(self.slot);
+    }
+}
+
+// maps a stack of `pages` pages with a guard page below it,
+// returns `None` when all slots are taken or the frames run out
+pub fn allocate_kernel_stack(pages: usize) -> Option<KernelStack> {
+    assert!(
+        (1..=MAX_STACK_PAGES).contains(&pages),
+        "kernel stacks have 1 to {} pages",
+        MAX_STACK_PAGES
+    );
+
+    let slot = take_slot()?;
+    let bottom = slot_start(slot) + (STACK_SLOT_PAGES - pages as u64);
+    let mapped = KERNEL_MEMORY.lock().as_mut().map(|kernel_memory| {
+        kernel_memory.map_range(bottom.start_address(), pages as u64 * 4096, STACK_FLAGS)
+    });
+
+    if !matches!(mapped, Some(Ok(()))) {
+        release_slot(slot);
+
+        return None;
+    }
+
+    Some(KernelStack {
+        slot,
+        bottom,
+        pages,
+    })
+}
+
+fn slot_start(slot: usize) -> Page {
+    Page::containing_address(VirtAddr::new(
+        KERNEL_STACKS_START + slot as u64 * STACK_SLOT_PAGES * 4096,
+    ))
+}
+
+fn take_slot() -> Option<usize> {
+    let mut slots = STACK_SLOTS.lock();
+    let slot = (0..MAX_KERNEL_STACKS).find(|&slot| slots.used & (1 << slot) == 0)?;
+
+    // the region covers the guard pages too, so faults in them are reported as stack faults
+    if !slots.reserved {
+        vma::reserve_at(
+            "kernel stacks",
+            VirtAddr::new(KERNEL_STACKS_START),
+            MAX_KERNEL_STACKS as u64 * STACK_SLOT_PAGES * 4096,
+            STACK_FLAGS,
+            VmaBacking::External,
+        )
+        .expect("kernel stack range is already reserved");
+        slots.reserved = true;
+    }
+    slots.used |= 1 << slot;
+
+    Some(slot)
+}
+
+fn release_slot(slot: usize) {
+    STACK_SLOTS.lock().used &= !(1 << slot);
+}
@@ -0,0 +1,284 @@
+use core::fmt;
+
+use spin::Mutex;
+use x86_64::{
+    VirtAddr,
+    structures::paging::{Page, PageSize, PageTableFlags, Size2MiB, Size4KiB, mapper::MapToError},
+};
+
+use crate::memory::KERNEL_MEMORY;
+use crate::serial_println;
+
+// regions reserved without a fixed address are placed in here
+pub const VMA_AREA_START: u64 = 0x_6666_0000_0000;
+pub const VMA_AREA_END: u64 = 0x_6667_0000_0000;
+const MAX_VMAS: usize = 64;
+const PAGE_SIZE: u64 = 4096;
+
+// lock order: VMAS before KERNEL_MEMORY
+static VMAS: Mutex<VmaTable> = Mutex::new(VmaTable::new());
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VmaBacking {
+    // frames are allocated by `map` and freed when the region is released
+    Anonymous,
+    // the pages are mapped and unmapped by the owner of the region, e.g. the heap
+    External,
+    // nothing is mapped up front, the page fault handler maps a zeroed frame on first access
+    // and the frames are freed when the region is released
+    Lazy,
+}
+
+// a named, page aligned range of kernel virtual memory
+#[derive(Debug, Clone, Copy)]
+pub struct Vma {
+    pub name: &'static str,
+    pub start: VirtAddr,
+    pub size: u64,
+    pub flags: PageTableFlags,
+    pub backing: VmaBacking,
+}
+
+impl Vma {
+    pub fn end(&self) -> VirtAddr {
+        self.start + self.size
+    }
+
+    pub fn contains(&self, addr: VirtAddr) -> bool {
+        self.start <= addr && addr < self.end()
+    }
+
+    fn overlaps(&self, start: VirtAddr, end: VirtAddr) -> bool {
+        self.start < end && start < self.end()
+    }
+}
+
+impl fmt::Display for Vma {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {:#x}-{:#x} ({} KiB, {:?}, {:?})",
+            self.name,
+            self.start.as_u64(),
+            self.end().as_u64(),
+            self.size / 1024,
+            self.backing,
+            self.flags
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VmaError {
+    Unaligned,
+    Overlap,
+    NoSpace,
+    TooManyRegions,
+    NotFound,
+    NotAnonymous,
+    AlreadyMapped,
+    OutOfFrames,
+}
+
+impl From<MapToError<Size4KiB>> for VmaError {
+    fn from(err: MapToError<Size4KiB>) -> Self {
+        match err {
+            MapToError::FrameAllocationFailed => VmaError::OutOfFrames,
+            MapToError::ParentEntryHugePage | MapToError::PageAlreadyMapped(_) => {
+                VmaError::AlreadyMapped
+            }
+        }
+    }
+}
+
+// fixed size so that reserving a region never touches the heap
+struct VmaTable {
+    regions: [Option<Vma>; MAX_VMAS],
+}
+
+impl VmaTable {
+    const fn new() -> Self {
+        VmaTable {
+            regions: [None; MAX_VMAS],
+        }
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &Vma> {
+        self.regions.iter().flatten()
+    }
+
+    fn insert(&mut self, vma: Vma) -> Result<Vma, VmaError> {
+        if self
+            .iter()
+            .any(|region| region.overlaps(vma.start, vma.end()))
+        {
+            return Err(VmaError::Overlap);
+        }
+
+        let slot = self
+            .regions
+            .iter_mut()
+            .find(|slot| slot.is_none())
+            .ok_or(VmaError::TooManyRegions)?;
+        *slot = Some(vma);
+
+        Ok(vma)
+    }
+
+    fn remove(&mut self, start: VirtAddr) -> Option<Vma> {
+        self.regions
+            .iter_mut()
+            .find(|slot| slot.is_some_and(|region| region.start == start))?
+            .take()
+    }
+
+    fn get(&self, start: VirtAddr) -> Option<Vma> {
+        self.iter().find(|region| region.start == start).copied()
+    }
+
+    fn find(&self, addr: VirtAddr) -> Option<Vma> {
+        self.iter().find(|region| region.contains(addr)).copied()
+    }
+
+    // first fit in the VMA area, leaving an unmapped page after every region
+    fn find_free(&self, size: u64, align: u64) -> Option<VirtAddr> {
+        let mut start = VirtAddr::new(VMA_AREA_START).align_up(align);
+
+        loop {
+            let end = start + size;
+            if end.as_u64() > VMA_AREA_END {
+                return None;
+            }
+
+            match self.iter().find(|region| region.overlaps(start, end)) {
+                Some(region) => start = (region.end() + PAGE_SIZE).align_up(align),
+                None => return Some(start),
+            }
+        }
+    }
+}
+
+// reserves `size` bytes somewhere in the VMA area, nothing is mapped yet
+pub fn reserve(
+    name: &'static str,
+    size: u64,
+    flags: PageTableFlags,
+    backing: VmaBacking,
+) -> Result<Vma, VmaError> {
+    let size = page_align(size);
+    // large regions are aligned so that they can be mapped with huge pages
+    let align = if size >= Size2MiB::SIZE {
+        Size2MiB::SIZE
+    } else {
+        PAGE_SIZE
+    };
+
+    let mut vmas = VMAS.lock();
+    let start = vmas.find_free(size, align).ok_or(VmaError::NoSpace)?;
+
+    vmas.insert(Vma {
+        name,
+        start,
+        size,
+        flags,
+        backing,
+    })
+}
+
+// reserves a region at a fixed address, e.g. for the heap
+pub fn reserve_at(
+    name: &'static str,
+    start: VirtAddr,
+    size: u64,
+    flags: PageTableFlags,
+    backing: VmaBacking,
+) -> Result<Vma, VmaError> {
+    if !start.is_aligned(PAGE_SIZE) {
+        return Err(VmaError::Unaligned);
+    }
+
+    VMAS.lock().insert(Vma {
+        name,
+        start,
+        size: page_align(size),
+        flags,
+        backing,
+    })
+}
+
+// backs every page of the anonymous region starting at `start` with a new frame
+pub fn map(start: VirtAddr) -> Result<(), VmaError> {
+    let vmas = VMAS.lock();
+    let vma = vmas.get(start).ok_or(VmaError::NotFound)?;
+    if vma.backing != VmaBacking::Anonymous {
+        return Err(VmaError::NotAnonymous);
+    }
+
+    KERNEL_MEMORY
+        .lock()
+        .as_mut()
+        .expect("kernel memory is not initialized")
+        .map_range(vma.start, vma.size, vma.flags)?;
+
+    Ok(())
+}
+
+// removes the region starting at `start`, the pages of an anonymous or lazy region are unmapped
+// and their frames freed
+pub fn release(start: VirtAddr) -> Result<Vma, VmaError> {
+    let mut vmas = VMAS.lock();
+    let vma = vmas.remove(start).ok_or(VmaError::NotFound)?;
+
+    if vma.backing != VmaBacking::External
+        && let Some(kernel_memory) = KERNEL_MEMORY.lock().as_mut()
+    {
+        kernel_memory.unmap_range(vma.start, vma.size);
+    }
+
+    Ok(vma)
+}
+
+// the region that contains `addr`
+pub fn find(addr: VirtAddr) -> Option<Vma> {
+    VMAS.lock().find(addr)
+}
+
+// like `find`, but gives up instead of spinning when the regions are locked,
+// so it can be used from exception handlers
+pub fn try_find(addr: VirtAddr) -> Option<Vma> {
+    VMAS.try_lock()?.find(addr)
+}
+
+// maps a zeroed frame at `addr` if it lies in a lazy region, called by the page fault handler
+// for faults on pages that aren't present
+// the faulting code may hold the locks, so this gives up instead of spinning on them
+pub fn handle_lazy_fault(addr: VirtAddr) -> bool {
+    let Some(vmas) = VMAS.try_lock() else {
+        return false;
+    };
+    let Some(vma) = vmas
+        .find(addr)
+        .filter(|vma| vma.backing == VmaBacking::Lazy)
+    else {
+        return false;
+    };
+
+    let Some(mut kernel_memory) = KERNEL_MEMORY.try_lock() else {
+        return false;
+    };
+    kernel_memory.as_mut().is_some_and(|kernel_memory| {
+        kernel_memory
+            .map_zeroed_page(Page::containing_address(addr), vma.flags)
+            .is_ok()
+    })
+}
+
+pub fn print_regions() {
+    for vma in VMAS.lock().iter() {
+        serial_println!("  {}", vma);
+    }
+}
+
+fn page_align(size: u64) -> u64 {
+    size.div_ceil(PAGE_SIZE) * PAGE_SIZE
+}
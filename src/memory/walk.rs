@@ -0,0 +1,257 @@
+use core::fmt;
+
+use x86_64::{
+    PhysAddr, VirtAddr,
+    registers::control::Cr3,
+    structures::paging::{PageTable, PageTableFlags, PageTableIndex, page_table::PageTableLevel},
+};
+
+use crate::memory;
+use crate::serial_println;
+
+// these tell nothing about a mapping and would split every run that was touched
+const IGNORED_FLAGS: PageTableFlags = PageTableFlags::ACCESSED
+    .union(PageTableFlags::DIRTY)
+    .union(PageTableFlags::HUGE_PAGE);
+
+// one entry the MMU looks at while translating an address
+#[derive(Debug, Clone, Copy)]
+pub struct WalkStep {
+    pub level: PageTableLevel,
+    pub index: PageTableIndex,
+    pub flags: PageTableFlags,
+    // the next table, or the frame if this entry maps the page
+    pub addr: PhysAddr,
+}
+
+impl fmt::Display for WalkStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "P{}[{:3}]: ", self.level as u8, u16::from(self.index))?;
+        if self.flags.contains(PageTableFlags::PRESENT) {
+            write!(f, "{:#x} {:?}", self.addr.as_u64(), self.flags)
+        } else {
+            write!(f, "not present")
+        }
+    }
+}
+
+// the result of walking the active page tables for an address, from the level 4 entry down to
+// the entry that ended the walk
+pub struct Translation {
+    pub addr: VirtAddr,
+    steps: [Option<WalkStep>; 4],
+}
+
+impl Translation {
+    pub fn steps(&self) -> impl Iterator<Item = &WalkStep> {
+        self.steps.iter().flatten()
+    }
+
+    // the level whose entry ended the walk, by mapping the page or by not being present
+    pub fn level(&self) -> PageTableLevel {
+        self.last_step().level
+    }
+
+    pub fn is_mapped(&self) -> bool {
+        self.last_step().flags.contains(PageTableFlags::PRESENT)
+    }
+
+    pub fn phys_addr(&self) -> Option<PhysAddr> {
+        let offset = self.addr.as_u64() & (self.page_size()? - 1);
+        Some(self.last_step().addr + offset)
+    }
+
+    pub fn flags(&self) -> Option<PageTableFlags> {
+        self.is_mapped().then(|| self.last_step().flags)
+    }
+
+    // 4 KiB, 2 MiB or 1 GiB, depending on the level that maps the page
+    pub fn page_size(&self) -> Option<u64> {
+        self.is_mapped()
+            .then(|| self.level().entry_address_space_alignment())
+    }
+
+    fn last_step(&self) -> &WalkStep {
+        self.steps().last().expect("a walk has at least one step")
+    }
+}
+
+impl fmt::Display for Translation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for step in self.steps() {
+            writeln!(f, "  {}", step)?;
+        }
+
+        match (self.phys_addr(), self.page_size()) {
+            (Some(phys_addr), Some(page_size)) => writeln!(
+                f,
+                "  {:#x} -> {:#x} ({} KiB page)",
+                self.addr.as_u64(),
+                phys_addr.as_u64(),
+                page_size / 1024
+            ),
+            _ => writeln!(
+                f,
+                "  {:#x} is not mapped, the walk ended at P{}",
+                self.addr.as_u64(),
+                self.level() as u8
+            ),
+        }
+    }
+}
+
+// a run of pages that are contiguous in virtual and physical memory and have the same flags
+#[derive(Debug, Clone, Copy)]
+pub struct Mapping {
+    pub start: VirtAddr,
+    pub phys: PhysAddr,
+    pub size: u64,
+    pub flags: PageTableFlags,
+}
+
+impl Mapping {
+    // merges `next` into this run if it continues it
+    fn extend(&mut self, next: &Mapping) -> bool {
+        let continues = self.start.as_u64().wrapping_add(self.size) == next.start.as_u64()
+            && self.phys + self.size == next.phys
+            && self.flags == next.flags;
+        if continues {
+            self.size += next.size;
+        }
+
+        continues
+    }
+}
+
+impl fmt::Display for Mapping {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:#x}-{:#x} -> {:#x} ({} KiB, {:?})",
+            self.start.as_u64(),
+            self.start.as_u64().wrapping_add(self.size),
+            self.phys.as_u64(),
+            self.size / 1024,
+            self.flags
+        )
+    }
+}
+
+// walks the active page tables for `addr`, `None` before `memory::init` recorded where physical
+// memory is mapped
+// the tables are read directly without taking `KERNEL_MEMORY`, so this also works in exception
+// handlers and while the lock is held
+pub fn translate(addr: VirtAddr) -> Option<Translation> {
+    let phys_offset = memory::physical_memory_offset()?;
+
+    let mut translation = Translation {
+        addr,
+        steps: [None; 4],
+    };
+    let mut table_addr = Cr3::read().0.start_address();
+    let mut level = PageTableLevel::Four;
+    for step in &mut translation.steps {
+        let index = addr.page_table_index(level);
+        let entry = &table_at(phys_offset, table_addr)[index];
+        *step = Some(WalkStep {
+            level,
+            index,
+            flags: entry.flags(),
+            addr: entry.addr(),
+        });
+
+        if !entry.flags().contains(PageTableFlags::PRESENT) || maps_page(level, entry.flags()) {
+            break;
+        }
+        table_addr = entry.addr();
+        level = level
+            .next_lower_level()
+            .expect("level 1 entries always map a page");
+    }
+
+    Some(translation)
+}
+
+// calls `f` with every present mapping in the active page tables, in address order and merged
+// into contiguous runs
+pub fn for_each_mapping(mut f: impl FnMut(Mapping)) {
+    let Some(phys_offset) = memory::physical_memory_offset() else {
+        return;
+    };
+
+    let mut run: Option<Mapping> = None;
+    let level_4_table = Cr3::read().0.start_address();
+    visit_table(
+        phys_offset,
+        level_4_table,
+        PageTableLevel::Four,
+        0,
+        &mut |page| {
+            if run.as_mut().is_some_and(|run| run.extend(&page)) {
+                return;
+            }
+            if let Some(finished) = run.replace(page) {
+                f(finished);
+            }
+        },
+    );
+
+    if let Some(run) = run {
+        f(run);
+    }
+}
+
+// prints every present mapping of the active page tables to serial
+pub fn dump_mappings() {
+    let mut mappings = 0;
+    for_each_mapping(|mapping| {
+        serial_println!("  {}", mapping);
+        mappings += 1;
+    });
+    serial_println!("  {} mappings", mappings);
+}
+
+fn visit_table(
+    phys_offset: VirtAddr,
+    table_addr: PhysAddr,
+    level: PageTableLevel,
+    table_start: u64,
+    page: &mut impl FnMut(Mapping),
+) {
+    let shift = 12 + 9 * (level as u64 - 1);
+
+    for (index, entry) in table_at(phys_offset, table_addr).iter().enumerate() {
+        let flags = entry.flags();
+        if !flags.contains(PageTableFlags::PRESENT) {
+            continue;
+        }
+
+        let start = table_start | (index as u64) << shift;
+        match level.next_lower_level() {
+            Some(next_level) if !maps_page(level, flags) => {
+                visit_table(phys_offset, entry.addr(), next_level, start, page)
+            }
+            _ => page(Mapping {
+                // sign extends the upper half
+                start: VirtAddr::new_truncate(start),
+                phys: entry.addr(),
+                size: level.entry_address_space_alignment(),
+                flags: flags.difference(IGNORED_FLAGS),
+            }),
+        }
+    }
+}
+
+// whether a present entry maps a page instead of pointing to the next table
+// in level 1 entries the huge page bit selects the memory type instead
+pub(crate) fn maps_page(level: PageTableLevel, flags: PageTableFlags) -> bool {
+    match level {
+        PageTableLevel::One => true,
+        PageTableLevel::Two | PageTableLevel::Three => flags.contains(PageTableFlags::HUGE_PAGE),
+        PageTableLevel::Four => false,
+    }
+}
+
+fn table_at(phys_offset: VirtAddr, table_addr: PhysAddr) -> &'static PageTable {
+    unsafe { &*(phys_offset + table_addr.as_u64()).as_ptr() }
+}
@@ -1,4 +1,12 @@
+mod block_on;
 pub mod executor;
 pub mod keyboard;
+pub mod select;
 pub mod simple_executor;
 pub mod task_struct;
+pub mod timeout;
+pub mod timer;
+
+#[doc(hidden)]
+pub use block_on::block_on;
+pub use timeout::{Elapsed, Timeout, timeout};
@@ -0,0 +1,117 @@
+// `select!` and `join!` for tasks, usable in any `async` block the executor runs
+//
+// both take the futures by value and poll them in place inside the calling task, so a branch
+// is woken through the task's own waker (a timer, the keyboard, ...) and nothing is spawned
+// or allocated
+
+// waits for the first of several futures, drops the others and then runs the winner's handler
+// with its output
+//
+//     select! {
+//         key = scancodes.next() => handle(key),
+//         _ = timer::sleep(Duration::from_secs(5)) => println!("no key pressed"),
+//     }
+//
+// the branches are polled in order, so when several are ready at once the first one wins
+// patterns have to be irrefutable
+#[macro_export]
+macro_rules! select {
+    ($($pat:pat = $future:expr => $handler:expr),+ $(,)?) => {
+        $crate::__task_combinator!(@select_outputs [] $($pat = $future => $handler,)+)
+    };
+}
+
+// waits for all of the futures and evaluates to a tuple of their outputs
+//
+//     let (a, b) = join!(first(), second());
+#[macro_export]
+macro_rules! join {
+    ($($future:expr),+ $(,)?) => {
+        $crate::__task_combinator!(@join_outputs [] $($future,)+)
+    };
+}
+
+// every arm that recurses declares its own `output` or `future`, hygiene keeps them apart, so
+// each branch ends up with variables of its own
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __task_combinator {
+    // select: a slot for every branch's output, outside the block the futures live in
+    (@select_outputs [$($branches:tt)*] $pat:pat = $future:expr => $handler:expr, $($rest:tt)*) => {{
+        let mut output = ::core::option::Option::None;
+        $crate::__task_combinator!(
+            @select_outputs [$($branches)* (output, $future, $pat, $handler)] $($rest)*
+        )
+    }};
+    (@select_outputs [$($branches:tt)*]) => {{
+        $crate::__task_combinator!(@select_futures [] $($branches)*);
+        $crate::__task_combinator!(@select_handlers $($branches)*)
+    }};
+
+    // select: pin the futures and poll them until one of them fills its slot, the block ends
+    // before any handler runs, which drops the futures that lost
+    (@select_futures [$($polled:tt)*] ($output:ident, $future:expr, $pat:pat, $handler:expr) $($rest:tt)*) => {{
+        let mut future = ::core::pin::pin!($future);
+        $crate::__task_combinator!(@select_futures [$($polled)* ($output, future)] $($rest)*)
+    }};
+    (@select_futures [$(($output:ident, $future:ident))*]) => {
+        ::core::future::poll_fn(|cx| {
+            $(
+                if let ::core::task::Poll::Ready(value) =
+                    ::core::future::Future::poll($future.as_mut(), cx)
+                {
+                    $output = ::core::option::Option::Some(value);
+                    return ::core::task::Poll::Ready(());
+                }
+            )*
+            ::core::task::Poll::Pending
+        })
+        .await
+    };
+
+    (@select_handlers $(($output:ident, $future:expr, $pat:pat, $handler:expr))*) => {
+        $(
+            if let ::core::option::Option::Some($pat) = $output {
+                $handler
+            } else
+        )*
+        {
+            ::core::unreachable!("select! finished without a branch completing")
+        }
+    };
+
+    // join: a slot for every output, filled as the futures complete
+    (@join_outputs [$($branches:tt)*] $future:expr, $($rest:tt)*) => {{
+        let mut output = ::core::option::Option::None;
+        $crate::__task_combinator!(@join_outputs [$($branches)* (output, $future)] $($rest)*)
+    }};
+    (@join_outputs [$($branches:tt)*]) => {
+        $crate::__task_combinator!(@join_futures [] $($branches)*)
+    };
+
+    (@join_futures [$($polled:tt)*] ($output:ident, $future:expr) $($rest:tt)*) => {{
+        let mut future = ::core::pin::pin!($future);
+        $crate::__task_combinator!(@join_futures [$($polled)* ($output, future)] $($rest)*)
+    }};
+    (@join_futures [$(($output:ident, $future:ident))*]) => {{
+        // a future that completed isn't polled again
+        ::core::future::poll_fn(|cx| {
+            let mut done = true;
+            $(
+                if $output.is_none() {
+                    match ::core::future::Future::poll($future.as_mut(), cx) {
+                        ::core::task::Poll::Ready(value) => $output = ::core::option::Option::Some(value),
+                        ::core::task::Poll::Pending => done = false,
+                    }
+                }
+            )*
+            if done {
+                ::core::task::Poll::Ready(())
+            } else {
+                ::core::task::Poll::Pending
+            }
+        })
+        .await;
+        ($($output.unwrap(),)*)
+    }};
+}
@@ -5,6 +5,10 @@ use core::{
     task::{Context, Poll},
 };
 
+// id of the task that is being polled right now, `NO_TASK` outside of any task
+static CURRENT_TASK: AtomicU64 = AtomicU64::new(NO_TASK);
+const NO_TASK: u64 = u64::MAX;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct TaskId(u64);
 
@@ -13,6 +17,13 @@ impl TaskId {
         static NEXT_ID: AtomicU64 = AtomicU64::new(0);
         TaskId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
     }
+
+    pub fn current() -> Option<TaskId> {
+        match CURRENT_TASK.load(Ordering::Relaxed) {
+            NO_TASK => None,
+            id => Some(TaskId(id)),
+        }
+    }
 }
 
 // can store different types of Futures with the `dyn` keyword == dynamically dispatched methods
@@ -30,6 +41,10 @@ impl Task {
     }
 
     pub fn poll(&mut self, context: &mut Context) -> Poll<()> {
-        self.future.as_mut().poll(context)
+        CURRENT_TASK.store(self.id.0, Ordering::Relaxed);
+        let poll = self.future.as_mut().poll(context);
+        CURRENT_TASK.store(NO_TASK, Ordering::Relaxed);
+
+        poll
     }
 }
@@ -0,0 +1,244 @@
+use alloc::vec::Vec;
+use core::{
+    pin::Pin,
+    task::{Context, Poll, Waker},
+    time::Duration,
+};
+
+use futures_util::Stream;
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+use crate::time;
+
+const WHEEL_SLOTS: usize = 64;
+
+// timers waiting for their deadline, woken from the timer interrupt
+// every lock is taken with interrupts disabled, so the interrupt handler never finds it held
+static WHEEL: Mutex<TimerWheel> = Mutex::new(TimerWheel::new());
+
+struct TimerEntry {
+    id: u64,
+    deadline: u64,
+    waker: Waker,
+    // woken by the interrupt handler, the entry stays until its task removes it
+    fired: bool,
+}
+
+// timers are hashed into the slot of their deadline tick, so a tick only looks at one slot
+// a timer more than a rotation away stays in its slot until its round comes
+struct TimerWheel {
+    slots: [Vec<TimerEntry>; WHEEL_SLOTS],
+    next_id: u64,
+}
+
+impl TimerWheel {
+    const fn new() -> Self {
+        TimerWheel {
+            slots: [const { Vec::new() }; WHEEL_SLOTS],
+            next_id: 0,
+        }
+    }
+
+    fn slot(deadline: u64) -> usize {
+        (deadline % WHEEL_SLOTS as u64) as usize
+    }
+
+    // adds a timer or updates the waker of the one with `id`, returns the id of the timer
+    fn register(&mut self, id: Option<u64>, deadline: u64, waker: &Waker) -> u64 {
+        let slot = &mut self.slots[Self::slot(deadline)];
+
+        if let Some(entry) = id.and_then(|id| slot.iter_mut().find(|entry| entry.id == id)) {
+            if !entry.waker.will_wake(waker) {
+                entry.waker = waker.clone();
+            }
+            return entry.id;
+        }
+
+        let id = self.next_id;
+        self.next_id += 1;
+        slot.push(TimerEntry {
+            id,
+            deadline,
+            waker: waker.clone(),
+            fired: false,
+        });
+
+        id
+    }
+
+    // removes the timer, whether it fired or not
+    // entries and wakers are only dropped here and in `register`, in task context, since dropping
+    // the last reference to a task's waker frees it and the interrupt handler must not free
+    // anything, an empty slot gives its memory back here too
+    fn cancel(&mut self, id: u64, deadline: u64) {
+        let slot = &mut self.slots[Self::slot(deadline)];
+        if let Some(index) = slot.iter().position(|entry| entry.id == id) {
+            slot.swap_remove(index);
+        }
+        if slot.is_empty() {
+            *slot = Vec::new();
+        }
+    }
+
+    fn wake_expired(&mut self, now: u64) {
+        let slot = &mut self.slots[Self::slot(now)];
+
+        for entry in slot.iter_mut() {
+            if !entry.fired && entry.deadline <= now {
+                entry.fired = true;
+                entry.waker.wake_by_ref();
+            }
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.slots
+            .iter()
+            .flatten()
+            .filter(|entry| !entry.fired)
+            .count()
+    }
+}
+
+// a deadline in ticks, registered in the wheel while a task waits for it
+// dropping it cancels the timer, so a future that loses a race leaves nothing behind
+struct Timer {
+    deadline: u64,
+    id: Option<u64>,
+}
+
+impl Timer {
+    fn new(deadline: u64) -> Self {
+        Timer { deadline, id: None }
+    }
+
+    fn poll_deadline(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        // checking the deadline and registering happen without a tick in between, so the wakeup
+        // can't be missed
+        interrupts::without_interrupts(|| {
+            let mut wheel = WHEEL.lock();
+
+            if time::ticks() >= self.deadline {
+                if let Some(id) = self.id.take() {
+                    wheel.cancel(id, self.deadline);
+                }
+                return Poll::Ready(());
+            }
+
+            self.id = Some(wheel.register(self.id, self.deadline, cx.waker()));
+            Poll::Pending
+        })
+    }
+
+    fn reset(&mut self, deadline: u64) {
+        self.cancel();
+        self.deadline = deadline;
+    }
+
+    fn cancel(&mut self) {
+        if let Some(id) = self.id.take() {
+            interrupts::without_interrupts(|| WHEEL.lock().cancel(id, self.deadline));
+        }
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+// completes once `duration` has passed, at the resolution of one tick
+pub fn sleep(duration: Duration) -> Sleep {
+    Sleep::until(deadline_after(duration))
+}
+
+pub struct Sleep {
+    timer: Timer,
+}
+
+impl Sleep {
+    // completes once the tick counter reaches `deadline`
+    pub fn until(deadline: u64) -> Sleep {
+        Sleep {
+            timer: Timer::new(deadline),
+        }
+    }
+
+    pub fn deadline(&self) -> u64 {
+        self.timer.deadline
+    }
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        self.get_mut().timer.poll_deadline(cx)
+    }
+}
+
+// yields once every period, the first time one period after it was created
+// periods that were missed because the task ran late are skipped instead of yielded in a burst
+pub struct Interval {
+    period: u64,
+    timer: Timer,
+}
+
+impl Interval {
+    pub fn new(period: Duration) -> Interval {
+        let period = time::duration_to_ticks(period).max(1);
+
+        Interval {
+            period,
+            timer: Timer::new(time::ticks() + period),
+        }
+    }
+
+    pub fn period_ticks(&self) -> u64 {
+        self.period
+    }
+}
+
+impl Stream for Interval {
+    type Item = ();
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<()>> {
+        let interval = self.get_mut();
+        if interval.timer.poll_deadline(cx).is_pending() {
+            return Poll::Pending;
+        }
+
+        let now = time::ticks();
+        let mut next = interval.timer.deadline + interval.period;
+        if next <= now {
+            next += (now - next) / interval.period * interval.period + interval.period;
+        }
+        interval.timer.reset(next);
+
+        Poll::Ready(Some(()))
+    }
+}
+
+// the number of timers tasks are waiting for
+pub fn pending_timers() -> usize {
+    interrupts::without_interrupts(|| WHEEL.lock().len())
+}
+
+// the current tick may already be partly over, so one more tick makes sure at least
+// `duration` passes
+fn deadline_after(duration: Duration) -> u64 {
+    if duration.is_zero() {
+        return time::ticks();
+    }
+
+    time::ticks()
+        .saturating_add(time::duration_to_ticks(duration))
+        .saturating_add(1)
+}
+
+// called by the timer interrupt handler for every tick
+pub(crate) fn wake_expired(now: u64) {
+    WHEEL.lock().wake_expired(now);
+}
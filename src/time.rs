@@ -0,0 +1,128 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+use crate::task::timer;
+
+pub mod clocksource;
+pub mod date_time;
+pub mod hpet;
+pub mod pit;
+pub mod rtc;
+pub mod tsc;
+pub mod wall_clock;
+
+pub use clocksource::{monotonic_now, udelay};
+pub use date_time::DateTime;
+
+// the tick rate `init` programs the PIT with
+pub const DEFAULT_TICK_HZ: u32 = 100;
+
+const NANOS_PER_SEC: u128 = 1_000_000_000;
+
+// timer interrupts since boot
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+// converts ticks to time, rebased whenever the tick rate changes so that uptime stays monotonic
+// it is only locked with interrupts disabled, `udelay` and `monotonic_now` read it from interrupt
+// handlers too
+static CLOCK: Mutex<TickClock> = Mutex::new(TickClock {
+    base_ticks: 0,
+    base_nanos: 0,
+    divisor: pit::DEFAULT_DIVISOR,
+});
+
+struct TickClock {
+    base_ticks: u64,
+    base_nanos: u64,
+    divisor: u32,
+}
+
+impl TickClock {
+    fn nanos_at(&self, ticks: u64) -> u64 {
+        let elapsed = (ticks - self.base_ticks) as u128 * self.divisor as u128 * NANOS_PER_SEC
+            / pit::PIT_FREQUENCY as u128;
+        self.base_nanos + elapsed as u64
+    }
+
+    fn ticks_for(&self, duration: Duration) -> u64 {
+        let ticks = (duration.as_nanos() * pit::PIT_FREQUENCY as u128)
+            .div_ceil(self.divisor as u128 * NANOS_PER_SEC);
+        ticks.try_into().unwrap_or(u64::MAX)
+    }
+}
+
+pub fn init() {
+    set_tick_frequency(DEFAULT_TICK_HZ);
+}
+
+// reprograms the PIT and returns the rate it actually runs at, which is as close to `hz` as the
+// divisor allows
+// timers that are already running keep their deadline in ticks
+pub fn set_tick_frequency(hz: u32) -> u32 {
+    let divisor = pit::divisor_for(hz);
+
+    interrupts::without_interrupts(|| {
+        let mut clock = CLOCK.lock();
+        let now = ticks();
+        *clock = TickClock {
+            base_ticks: now,
+            base_nanos: clock.nanos_at(now),
+            divisor,
+        };
+        pit::set_divisor(divisor);
+    });
+
+    pit::frequency_for(divisor)
+}
+
+pub fn tick_frequency() -> u32 {
+    pit::frequency_for(with_clock(|clock| clock.divisor))
+}
+
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+// halts until `ticks` more ticks have passed, interrupts have to be enabled
+// for tests, kernel code sleeps with `task::timer`
+#[doc(hidden)]
+pub fn wait_ticks(ticks: u64) {
+    let end = self::ticks() + ticks;
+    while self::ticks() < end {
+        x86_64::instructions::hlt();
+    }
+}
+
+// time since boot with the resolution of one tick, it only advances while interrupts are enabled
+pub fn uptime() -> Duration {
+    let nanos = with_clock(|clock| clock.nanos_at(ticks()));
+    Duration::from_nanos(nanos)
+}
+
+// the number of ticks that cover at least `duration` at the current tick rate
+pub fn duration_to_ticks(duration: Duration) -> u64 {
+    with_clock(|clock| clock.ticks_for(duration))
+}
+
+// the uptime at `ticks`
+fn nanos_at(ticks: u64) -> u64 {
+    with_clock(|clock| clock.nanos_at(ticks))
+}
+
+fn pit_divisor() -> u32 {
+    with_clock(|clock| clock.divisor)
+}
+
+fn with_clock<R>(f: impl FnOnce(&TickClock) -> R) -> R {
+    interrupts::without_interrupts(|| f(&CLOCK.lock()))
+}
+
+// called by the timer interrupt handler
+pub(crate) fn tick() {
+    let now = TICKS.fetch_add(1, Ordering::Relaxed) + 1;
+    clocksource::record_tick(now);
+    timer::wake_expired(now);
+}
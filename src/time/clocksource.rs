@@ -0,0 +1,235 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+
+use conquer_once::spin::OnceCell;
+use x86_64::instructions::interrupts;
+
+use crate::time::{self, hpet::Hpet, pit, tsc};
+
+const NANOS_PER_SEC: u128 = 1_000_000_000;
+// how long the TSC is measured against the HPET, and against the PIT, in ticks
+const HPET_CALIBRATION: Duration = Duration::from_millis(10);
+const PIT_CALIBRATION_TICKS: u64 = 10;
+
+// the high resolution clock, picked once by `init`
+static CLOCK: OnceCell<Clock> = OnceCell::uninit();
+
+// the tick count and clock reading of the latest tick, see `tick_drift`
+static LAST_TICK: AtomicU64 = AtomicU64::new(0);
+static LAST_TICK_NANOS: AtomicU64 = AtomicU64::new(0);
+
+// the counter `monotonic_now` reads
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClockSource {
+    // an invariant TSC, calibrated against the HPET or the PIT
+    Tsc,
+    Hpet,
+    // the tick counter, nothing better was found
+    Pit,
+}
+
+struct Clock {
+    counter: Counter,
+    // the uptime when the counter was at `start`, so that the clock counts from boot
+    base_nanos: u64,
+}
+
+enum Counter {
+    Tsc { start: u64, frequency: u64 },
+    Hpet { hpet: Hpet, start: u64 },
+    Pit,
+}
+
+impl Counter {
+    fn read(&self) -> u64 {
+        match self {
+            Counter::Tsc { .. } => tsc::read(),
+            Counter::Hpet { hpet, .. } => hpet.counter(),
+            Counter::Pit => 0,
+        }
+    }
+
+    fn source(&self) -> ClockSource {
+        match self {
+            Counter::Tsc { .. } => ClockSource::Tsc,
+            Counter::Hpet { .. } => ClockSource::Hpet,
+            Counter::Pit => ClockSource::Pit,
+        }
+    }
+}
+
+impl Clock {
+    // `None` for the PIT, which only counts ticks
+    fn high_resolution_nanos(&self) -> Option<u64> {
+        let elapsed = match &self.counter {
+            Counter::Tsc { start, frequency } => {
+                let counts = tsc::read().wrapping_sub(*start);
+                (u128::from(counts) * NANOS_PER_SEC / u128::from(*frequency)) as u64
+            }
+            Counter::Hpet { hpet, start } => hpet.counts_to_nanos(hpet.counter() - start),
+            Counter::Pit => return None,
+        };
+        Some(self.base_nanos + elapsed)
+    }
+}
+
+// picks the best clock there is: an invariant TSC, then the HPET, then the PIT ticks
+// maps the HPET and waits for a few ticks to calibrate, so kernel memory and interrupts have to
+// be up
+pub fn init() -> ClockSource {
+    if let Ok(clock) = CLOCK.try_get() {
+        return clock.counter.source();
+    }
+    assert!(
+        interrupts::are_enabled(),
+        "the clock is calibrated against timer interrupts"
+    );
+
+    let hpet = Hpet::find();
+    let mut counter = if tsc::is_invariant() {
+        let frequency = match &hpet {
+            Some(hpet) => tsc_frequency_from_hpet(hpet),
+            None => tsc_frequency_from_pit(),
+        };
+        Counter::Tsc {
+            start: 0,
+            frequency,
+        }
+    } else if let Some(hpet) = hpet {
+        Counter::Hpet { hpet, start: 0 }
+    } else {
+        Counter::Pit
+    };
+
+    // starting right after a tick lines the clock up with the tick clock
+    let ticks = wait_for_tick();
+    interrupts::without_interrupts(|| {
+        let now = counter.read();
+        match &mut counter {
+            Counter::Tsc { start, .. } | Counter::Hpet { start, .. } => *start = now,
+            Counter::Pit => {}
+        }
+
+        let source = counter.source();
+        CLOCK.init_once(|| Clock {
+            counter,
+            base_nanos: time::nanos_at(ticks),
+        });
+        source
+    })
+}
+
+pub fn source() -> Option<ClockSource> {
+    CLOCK.try_get().ok().map(|clock| clock.counter.source())
+}
+
+// the frequency of the TSC, if it is the clock source
+pub fn tsc_frequency() -> Option<u64> {
+    match CLOCK.try_get().ok()?.counter {
+        Counter::Tsc { frequency, .. } => Some(frequency),
+        _ => None,
+    }
+}
+
+// nanoseconds since boot, never going backwards
+// until `init` picked a clock, and with the PIT, the resolution is one tick
+pub fn monotonic_now() -> u64 {
+    CLOCK
+        .try_get()
+        .ok()
+        .and_then(Clock::high_resolution_nanos)
+        .unwrap_or_else(|| time::uptime().as_nanos() as u64)
+}
+
+// spins for at least `micros` microseconds, also with interrupts disabled
+pub fn udelay(micros: u64) {
+    let nanos = micros.saturating_mul(1000);
+
+    match CLOCK.try_get().ok() {
+        Some(clock) if clock.high_resolution_nanos().is_some() => {
+            let end = monotonic_now().saturating_add(nanos);
+            while monotonic_now() < end {
+                core::hint::spin_loop();
+            }
+        }
+        // without a better clock the PIT counter is polled, the tick count doesn't move while
+        // interrupts are disabled
+        _ => {
+            let clocks =
+                (u128::from(nanos) * u128::from(pit::PIT_FREQUENCY)).div_ceil(NANOS_PER_SEC);
+            pit::busy_wait(clocks as u64, time::pit_divisor());
+        }
+    }
+}
+
+// how far the tick clock was ahead of the high resolution clock at the latest tick, in
+// nanoseconds, negative if it is behind, for example because ticks were lost while interrupts
+// were disabled
+// `None` without a high resolution clock or before the first tick after `init`
+pub fn tick_drift() -> Option<i64> {
+    if source()? == ClockSource::Pit {
+        return None;
+    }
+
+    let (ticks, nanos) = interrupts::without_interrupts(|| {
+        (
+            LAST_TICK.load(Ordering::Relaxed),
+            LAST_TICK_NANOS.load(Ordering::Relaxed),
+        )
+    });
+    if nanos == 0 {
+        return None;
+    }
+
+    Some(time::nanos_at(ticks) as i64 - nanos as i64)
+}
+
+// called by the timer interrupt handler for every tick, it only reads the counter, the tick
+// clock is compared in `tick_drift`
+pub(crate) fn record_tick(ticks: u64) {
+    if let Some(nanos) = CLOCK.try_get().ok().and_then(Clock::high_resolution_nanos) {
+        LAST_TICK.store(ticks, Ordering::Relaxed);
+        LAST_TICK_NANOS.store(nanos, Ordering::Relaxed);
+    }
+}
+
+fn tsc_frequency_from_hpet(hpet: &Hpet) -> u64 {
+    let calibration_counts = HPET_CALIBRATION.as_nanos() as u64 * hpet.frequency() / 1_000_000_000;
+
+    let (tsc_counts, hpet_counts) = interrupts::without_interrupts(|| {
+        let hpet_start = hpet.counter();
+        let tsc_start = tsc::read();
+        let mut hpet_now = hpet_start;
+        while hpet_now - hpet_start < calibration_counts {
+            core::hint::spin_loop();
+            hpet_now = hpet.counter();
+        }
+        (tsc::read() - tsc_start, hpet_now - hpet_start)
+    });
+
+    (u128::from(tsc_counts) * NANOS_PER_SEC / u128::from(hpet.counts_to_nanos(hpet_counts))) as u64
+}
+
+fn tsc_frequency_from_pit() -> u64 {
+    let start_ticks = wait_for_tick();
+    let tsc_start = tsc::read();
+    while time::ticks() < start_ticks + PIT_CALIBRATION_TICKS {
+        x86_64::instructions::hlt();
+    }
+    let tsc_counts = tsc::read() - tsc_start;
+    let nanos = time::nanos_at(start_ticks + PIT_CALIBRATION_TICKS) - time::nanos_at(start_ticks);
+
+    (u128::from(tsc_counts) * NANOS_PER_SEC / u128::from(nanos)) as u64
+}
+
+// halts until the next tick and returns the new tick count
+fn wait_for_tick() -> u64 {
+    let start = time::ticks();
+    loop {
+        x86_64::instructions::hlt();
+        let now = time::ticks();
+        if now != start {
+            return now;
+        }
+    }
+}
@@ -0,0 +1,186 @@
+use core::fmt;
+
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+const SECS_PER_DAY: u64 = 86_400;
+// days from 0000-03-01, where the calendar arithmetic starts, to 1970-01-01
+const UNIX_EPOCH_DAYS: i64 = 719_468;
+const DAYS_PER_ERA: i64 = 146_097;
+
+// a UTC date and time of the proleptic Gregorian calendar, from 1970 on
+// the fields are ordered so that comparing two values compares the points in time
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+    pub nanosecond: u32,
+}
+
+impl DateTime {
+    pub const UNIX_EPOCH: DateTime = DateTime {
+        year: 1970,
+        month: 1,
+        day: 1,
+        hour: 0,
+        minute: 0,
+        second: 0,
+        nanosecond: 0,
+    };
+
+    pub fn from_unix_nanos(nanos: u64) -> DateTime {
+        let secs = nanos / NANOS_PER_SEC;
+        let days = secs / SECS_PER_DAY;
+        let secs_of_day = secs % SECS_PER_DAY;
+
+        // counts in 400 year eras starting on March 1st, so the leap day ends a year
+        let days = days as i64 + UNIX_EPOCH_DAYS;
+        let era = days.div_euclid(DAYS_PER_ERA);
+        let day_of_era = days - era * DAYS_PER_ERA;
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let shifted_month = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+        let month = if shifted_month < 10 {
+            shifted_month + 3
+        } else {
+            shifted_month - 9
+        };
+        let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+        DateTime {
+            year: year as u16,
+            month: month as u8,
+            day: day as u8,
+            hour: (secs_of_day / 3600) as u8,
+            minute: (secs_of_day / 60 % 60) as u8,
+            second: (secs_of_day % 60) as u8,
+            nanosecond: (nanos % NANOS_PER_SEC) as u32,
+        }
+    }
+
+    // nanoseconds since 1970-01-01 00:00:00 UTC, the date has to be valid
+    pub fn unix_nanos(&self) -> u64 {
+        assert!(self.is_valid(), "{:?} is not a valid date", self);
+
+        let year = i64::from(self.year) - i64::from(self.month <= 2);
+        let era = year.div_euclid(400);
+        let year_of_era = year - era * 400;
+        let shifted_month = (i64::from(self.month) + 9) % 12;
+        let day_of_year = (153 * shifted_month + 2) / 5 + i64::from(self.day) - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        let days = (era * DAYS_PER_ERA + day_of_era - UNIX_EPOCH_DAYS) as u64;
+
+        let secs = days * SECS_PER_DAY
+            + u64::from(self.hour) * 3600
+            + u64::from(self.minute) * 60
+            + u64::from(self.second);
+        secs * NANOS_PER_SEC + u64::from(self.nanosecond)
+    }
+
+    pub fn unix_seconds(&self) -> u64 {
+        self.unix_nanos() / NANOS_PER_SEC
+    }
+
+    // every field is in range, the date exists and lies after the epoch
+    pub fn is_valid(&self) -> bool {
+        self.year >= 1970
+            && (1..=12).contains(&self.month)
+            && (1..=days_in_month(self.year, self.month)).contains(&self.day)
+            && self.hour < 24
+            && self.minute < 60
+            && self.second < 60
+            && u64::from(self.nanosecond) < NANOS_PER_SEC
+    }
+}
+
+// ISO 8601 with milliseconds, for example 2024-02-29T13:05:09.250Z
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+            self.year,
+            self.month,
+            self.day,
+            self.hour,
+            self.minute,
+            self.second,
+            self.nanosecond / 1_000_000
+        )
+    }
+}
+
+pub fn is_leap_year(year: u16) -> bool {
+    year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400))
+}
+
+pub fn days_in_month(year: u16, month: u8) -> u8 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+#[test_case]
+fn test_unix_time_round_trips() {
+    assert_eq!(DateTime::from_unix_nanos(0), DateTime::UNIX_EPOCH);
+
+    // 2000-02-29T12:00:00.5Z
+    let leap_day = DateTime::from_unix_nanos(951_825_600_500_000_000);
+    assert_eq!(
+        (leap_day.year, leap_day.month, leap_day.day, leap_day.hour),
+        (2000, 2, 29, 12)
+    );
+    assert_eq!(leap_day.unix_nanos(), 951_825_600_500_000_000);
+
+    let end_of_2099 = DateTime {
+        year: 2099,
+        month: 12,
+        day: 31,
+        hour: 23,
+        minute: 59,
+        second: 59,
+        nanosecond: 0,
+    };
+    assert_eq!(
+        DateTime::from_unix_nanos(end_of_2099.unix_nanos()),
+        end_of_2099
+    );
+}
+
+#[test_case]
+fn test_date_time_is_formatted_as_iso_8601() {
+    use core::fmt::Write;
+
+    // the heap isn't set up for the library tests
+    struct Buffer {
+        bytes: [u8; 32],
+        len: usize,
+    }
+
+    impl Write for Buffer {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            let end = self.len + s.len();
+            self.bytes
+                .get_mut(self.len..end)
+                .ok_or(fmt::Error)?
+                .copy_from_slice(s.as_bytes());
+            self.len = end;
+            Ok(())
+        }
+    }
+
+    let mut buffer = Buffer {
+        bytes: [0; 32],
+        len: 0,
+    };
+    let date_time = DateTime::from_unix_nanos(1_709_211_909_250_000_000);
+    write!(buffer, "{}", date_time).unwrap();
+    assert_eq!(&buffer.bytes[..buffer.len], b"2024-02-29T13:05:09.250Z");
+}
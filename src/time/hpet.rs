@@ -0,0 +1,74 @@
+use x86_64::PhysAddr;
+
+use crate::acpi;
+use crate::memory::{self, mmio::MmioRegion};
+
+// the base address is a generic address structure at offset 40 of the ACPI table, its address
+// space id has to say system memory
+const ACPI_ADDRESS_SPACE: u64 = 40;
+const ACPI_BASE_ADDRESS: u64 = 44;
+const SYSTEM_MEMORY: u8 = 0;
+
+const CAPABILITIES: usize = 0x00;
+const CONFIGURATION: usize = 0x10;
+const MAIN_COUNTER: usize = 0xf0;
+const REGISTERS_SIZE: usize = 0x100;
+
+const COUNTER_64_BIT: u64 = 1 << 13;
+const ENABLE: u64 = 1;
+
+const FEMTOS_PER_SEC: u64 = 1_000_000_000_000_000;
+
+// the high precision event timer, only its main counter is used: it counts up at a fixed rate
+// of at least 10 MHz and never stops once enabled
+#[derive(Debug)]
+pub struct Hpet {
+    registers: MmioRegion,
+    period_femtos: u64,
+}
+
+impl Hpet {
+    // the HPET described by the ACPI tables, mapped and counting
+    // `None` if there is none or its counter is only 32 bits wide, which would wrap every few
+    // minutes
+    pub fn find() -> Option<Hpet> {
+        let table = acpi::find_table(b"HPET")?;
+        if acpi::read_field::<u8>(&table, ACPI_ADDRESS_SPACE)? != SYSTEM_MEMORY {
+            return None;
+        }
+        let base = acpi::read_field::<u64>(&table, ACPI_BASE_ADDRESS)?;
+
+        let registers = memory::map_mmio(PhysAddr::new(base), REGISTERS_SIZE).ok()?;
+        let capabilities: u64 = registers.read(CAPABILITIES);
+        let period_femtos = capabilities >> 32;
+        if capabilities & COUNTER_64_BIT == 0 || period_femtos == 0 {
+            return None;
+        }
+
+        let configuration: u64 = registers.read(CONFIGURATION);
+        registers.write(CONFIGURATION, configuration | ENABLE);
+
+        Some(Hpet {
+            registers,
+            period_femtos,
+        })
+    }
+
+    pub fn counter(&self) -> u64 {
+        self.registers.read(MAIN_COUNTER)
+    }
+
+    // the length of one count in femtoseconds
+    pub fn period_femtos(&self) -> u64 {
+        self.period_femtos
+    }
+
+    pub fn frequency(&self) -> u64 {
+        FEMTOS_PER_SEC / self.period_femtos
+    }
+
+    // nanoseconds between two counter values
+    pub fn counts_to_nanos(&self, counts: u64) -> u64 {
+        (u128::from(counts) * u128::from(self.period_femtos) / 1_000_000) as u64
+    }
+}
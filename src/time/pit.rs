@@ -0,0 +1,76 @@
+use x86_64::instructions::port::Port;
+
+// the PIT counts down from the divisor at this rate and fires IRQ 0 every time it reaches zero
+pub const PIT_FREQUENCY: u32 = 1_193_182;
+// what the firmware leaves behind, a divisor of 0 stands for 65536 (about 18.2 Hz)
+pub const DEFAULT_DIVISOR: u32 = 65536;
+
+const CHANNEL_0: u16 = 0x40;
+const COMMAND: u16 = 0x43;
+// channel 0, low byte then high byte, mode 2 (rate generator), binary counting
+const CHANNEL_0_RATE_GENERATOR: u8 = 0b0011_0100;
+const CHANNEL_0_LATCH: u8 = 0b0000_0000;
+
+// the divisor that comes closest to `hz`
+pub fn divisor_for(hz: u32) -> u32 {
+    assert!(hz > 0, "the timer frequency must not be 0");
+    ((PIT_FREQUENCY + hz / 2) / hz).clamp(1, DEFAULT_DIVISOR)
+}
+
+// the rate channel 0 fires at with `divisor`, rounded to whole hertz
+pub fn frequency_for(divisor: u32) -> u32 {
+    (PIT_FREQUENCY + divisor / 2) / divisor
+}
+
+// makes channel 0 fire every `divisor` input clocks
+pub(crate) fn set_divisor(divisor: u32) {
+    assert!((1..=DEFAULT_DIVISOR).contains(&divisor));
+    // 65536 doesn't fit and is written as 0
+    let [low, high] = (divisor as u16).to_le_bytes();
+
+    let mut command = Port::<u8>::new(COMMAND);
+    let mut channel_0 = Port::<u8>::new(CHANNEL_0);
+    unsafe {
+        command.write(CHANNEL_0_RATE_GENERATOR);
+        channel_0.write(low);
+        channel_0.write(high);
+    }
+}
+
+// the current count of channel 0, it runs from the divisor down to 1 and starts over
+pub(crate) fn read_count() -> u32 {
+    let mut command = Port::<u8>::new(COMMAND);
+    let mut channel_0 = Port::<u8>::new(CHANNEL_0);
+    let [low, high] = unsafe {
+        // latches the count, so the two bytes belong together
+        command.write(CHANNEL_0_LATCH);
+        [channel_0.read(), channel_0.read()]
+    };
+
+    match u16::from_le_bytes([low, high]) {
+        0 => DEFAULT_DIVISOR,
+        count => u32::from(count),
+    }
+}
+
+// spins until channel 0 counted `clocks` input clocks, works with interrupts disabled
+// the count has to be sampled at least once per period, which a loop of port reads always does
+pub(crate) fn busy_wait(clocks: u64, divisor: u32) {
+    let mut previous = read_count();
+    let mut elapsed = 0;
+
+    while elapsed < clocks {
+        core::hint::spin_loop();
+        let count = read_count();
+        elapsed += u64::from((previous + divisor - count) % divisor);
+        previous = count;
+    }
+}
+
+#[test_case]
+fn test_divisor_round_trips_common_rates() {
+    assert_eq!(divisor_for(100), 11932);
+    assert_eq!(frequency_for(divisor_for(100)), 100);
+    assert_eq!(frequency_for(divisor_for(1000)), 1000);
+    assert_eq!(divisor_for(1), DEFAULT_DIVISOR);
+}
@@ -0,0 +1,220 @@
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use spin::Mutex;
+use x86_64::instructions::{interrupts, port::Port};
+
+use crate::acpi;
+use crate::time::DateTime;
+
+const CMOS_ADDRESS: u16 = 0x70;
+const CMOS_DATA: u16 = 0x71;
+// bit 7 of the address port disables NMIs, it is left clear
+const NMI_DISABLE: u8 = 1 << 7;
+
+const SECONDS: u8 = 0x00;
+const MINUTES: u8 = 0x02;
+const HOURS: u8 = 0x04;
+const DAY_OF_MONTH: u8 = 0x07;
+const MONTH: u8 = 0x08;
+const YEAR: u8 = 0x09;
+const STATUS_A: u8 = 0x0a;
+const STATUS_B: u8 = 0x0b;
+
+const UPDATE_IN_PROGRESS: u8 = 1 << 7;
+const HOURS_24: u8 = 1 << 1;
+const BINARY: u8 = 1 << 2;
+const HOUR_PM: u8 = 1 << 7;
+
+// the FADT names the CMOS register that holds the century, 0 if there is none
+const FADT_CENTURY: u64 = 108;
+
+// the CMOS register that holds the century, 0 until `init` found one
+static CENTURY_REGISTER: AtomicU8 = AtomicU8::new(0);
+
+// the address port selects the register the data port reads, so the two accesses can't be
+// interleaved with another reader
+static CMOS: Mutex<Cmos> = Mutex::new(Cmos {
+    address: Port::new(CMOS_ADDRESS),
+    data: Port::new(CMOS_DATA),
+});
+
+struct Cmos {
+    address: Port<u8>,
+    data: Port<u8>,
+}
+
+impl Cmos {
+    fn read(&mut self, register: u8) -> u8 {
+        unsafe {
+            self.address.write(register & !NMI_DISABLE);
+            self.data.read()
+        }
+    }
+
+    fn update_in_progress(&mut self) -> bool {
+        self.read(STATUS_A) & UPDATE_IN_PROGRESS != 0
+    }
+
+    fn read_registers(&mut self) -> RtcRegisters {
+        while self.update_in_progress() {
+            core::hint::spin_loop();
+        }
+
+        RtcRegisters {
+            second: self.read(SECONDS),
+            minute: self.read(MINUTES),
+            hour: self.read(HOURS),
+            day: self.read(DAY_OF_MONTH),
+            month: self.read(MONTH),
+            year: self.read(YEAR),
+        }
+    }
+}
+
+// the time registers as the RTC stores them, in its BCD or binary and 12 or 24 hour format
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RtcRegisters {
+    pub second: u8,
+    pub minute: u8,
+    pub hour: u8,
+    pub day: u8,
+    pub month: u8,
+    pub year: u8,
+}
+
+impl RtcRegisters {
+    // the date and time the registers hold, given status register B and the century (which
+    // only some RTCs keep, `None` means the 21st)
+    pub fn decode(&self, status_b: u8, century: Option<u8>) -> DateTime {
+        let value = |raw: u8| {
+            if status_b & BINARY != 0 {
+                raw
+            } else {
+                bcd_to_binary(raw)
+            }
+        };
+
+        // in 12 hour mode the top bit of the hour marks the afternoon, and midnight is 12 AM
+        let mut hour = value(self.hour & !HOUR_PM);
+        if status_b & HOURS_24 == 0 {
+            hour %= 12;
+            if self.hour & HOUR_PM != 0 {
+                hour += 12;
+            }
+        }
+
+        let century = century.map_or(20, value);
+        DateTime {
+            year: u16::from(century) * 100 + u16::from(value(self.year)),
+            month: value(self.month),
+            day: value(self.day),
+            hour,
+            minute: value(self.minute),
+            second: value(self.second),
+            nanosecond: 0,
+        }
+    }
+}
+
+// looks up the century register in the ACPI tables, without it the RTC is taken to count
+// years of the 21st century
+pub fn init() {
+    let fadt = acpi::find_table(b"FACP");
+    let register = fadt.and_then(|fadt| acpi::read_field::<u8>(&fadt, FADT_CENTURY));
+    CENTURY_REGISTER.store(register.unwrap_or(0), Ordering::Relaxed);
+}
+
+// the current date and time of the RTC, to the second, `None` if the registers don't hold a
+// valid date, for example because the CMOS battery ran out
+pub fn read() -> Option<DateTime> {
+    let century_register = match CENTURY_REGISTER.load(Ordering::Relaxed) {
+        0 => None,
+        register => Some(register),
+    };
+
+    interrupts::without_interrupts(|| {
+        let mut cmos = CMOS.lock();
+
+        let registers = read_stable(&mut cmos);
+        let century = century_register.map(|register| cmos.read(register));
+        let status_b = cmos.read(STATUS_B);
+        Some(registers.decode(status_b, century)).filter(DateTime::is_valid)
+    })
+}
+
+// the time registers as they are, for reporting an RTC that `read` can't make sense of
+pub fn read_registers() -> RtcRegisters {
+    interrupts::without_interrupts(|| read_stable(&mut CMOS.lock()))
+}
+
+// the registers are read until two reads in a row agree, so an update that starts in between
+// doesn't tear the result
+fn read_stable(cmos: &mut Cmos) -> RtcRegisters {
+    let mut registers = cmos.read_registers();
+    loop {
+        let again = cmos.read_registers();
+        if again == registers {
+            return registers;
+        }
+        registers = again;
+    }
+}
+
+fn bcd_to_binary(bcd: u8) -> u8 {
+    (bcd >> 4) * 10 + (bcd & 0x0f)
+}
+
+#[test_case]
+fn test_registers_are_decoded_in_every_mode() {
+    // 2024-02-29 13:05:09 in BCD, 12 hour mode
+    let bcd_12 = RtcRegisters {
+        second: 0x09,
+        minute: 0x05,
+        hour: HOUR_PM | 0x01,
+        day: 0x29,
+        month: 0x02,
+        year: 0x24,
+    };
+    let expected = DateTime {
+        year: 2024,
+        month: 2,
+        day: 29,
+        hour: 13,
+        minute: 5,
+        second: 9,
+        nanosecond: 0,
+    };
+    assert_eq!(bcd_12.decode(0, None), expected);
+    assert_eq!(bcd_12.decode(0, Some(0x20)), expected);
+
+    let binary_24 = RtcRegisters {
+        hour: 13,
+        day: 29,
+        year: 24,
+        ..bcd_12
+    };
+    assert_eq!(binary_24.decode(BINARY | HOURS_24, Some(20)), expected);
+
+    // 12 AM is midnight, 12 PM is noon
+    let midnight = RtcRegisters {
+        hour: 0x12,
+        ..bcd_12
+    };
+    assert_eq!(midnight.decode(0, None).hour, 0);
+    let noon = RtcRegisters {
+        hour: HOUR_PM | 0x12,
+        ..bcd_12
+    };
+    assert_eq!(noon.decode(0, None).hour, 12);
+
+    // a cleared CMOS doesn't decode to a valid date
+    let cleared = RtcRegisters {
+        second: 0,
+        minute: 0,
+        hour: 0,
+        day: 0,
+        month: 0,
+        year: 0,
+    };
+    assert!(!cleared.decode(0, None).is_valid());
+}
@@ -0,0 +1,12 @@
+use core::arch::x86_64::{__cpuid, _rdtsc};
+
+// an invariant TSC runs at the same rate in every power and frequency state, CPUID 0x8000_0007
+// reports it in bit 8 of EDX
+pub fn is_invariant() -> bool {
+    let max_extended_leaf = __cpuid(0x8000_0000).eax;
+    max_extended_leaf >= 0x8000_0007 && __cpuid(0x8000_0007).edx & (1 << 8) != 0
+}
+
+pub fn read() -> u64 {
+    unsafe { _rdtsc() }
+}
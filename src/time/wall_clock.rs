@@ -0,0 +1,71 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+
+use crate::serial_println;
+use crate::time::{DateTime, monotonic_now, rtc};
+
+// the wall clock time at boot, in nanoseconds since the epoch, 0 until `init` read the RTC
+static BOOT_UNIX_NANOS: AtomicU64 = AtomicU64::new(0);
+
+// a ticking RTC starts a new second within this, a bit more than a second to allow for the
+// resolution of the monotonic clock
+const SECOND_CHANGE_TIMEOUT: Duration = Duration::from_millis(1100);
+
+// sets the wall clock from the RTC and returns the time it was set to
+// the RTC only counts seconds, so this waits for its next second to begin, up to a second,
+// and pins that moment to the monotonic clock
+// if the RTC doesn't hold a valid date the wall clock counts from the epoch at boot, if it
+// doesn't tick the first reading is taken as is
+pub fn init() -> DateTime {
+    rtc::init();
+
+    let Some(first) = rtc::read() else {
+        return start_at_epoch();
+    };
+    let deadline = monotonic_now() + SECOND_CHANGE_TIMEOUT.as_nanos() as u64;
+    let (date_time, now) = loop {
+        let date_time = rtc::read();
+        let now = monotonic_now();
+        match date_time {
+            Some(date_time) if date_time != first => break (date_time, now),
+            Some(_) if now >= deadline => {
+                serial_println!(
+                    "WARNING: the RTC doesn't tick, the wall clock may be off by a second"
+                );
+                break (first, now);
+            }
+            Some(_) => core::hint::spin_loop(),
+            None => return start_at_epoch(),
+        }
+    };
+
+    let boot = date_time.unix_nanos().saturating_sub(now).max(1);
+    BOOT_UNIX_NANOS.store(boot, Ordering::Relaxed);
+
+    date_time
+}
+
+fn start_at_epoch() -> DateTime {
+    serial_println!(
+        "WARNING: the RTC holds no valid date ({:?}), the wall clock starts at the epoch",
+        rtc::read_registers()
+    );
+    BOOT_UNIX_NANOS.store(1, Ordering::Relaxed);
+
+    DateTime::UNIX_EPOCH
+}
+
+// nanoseconds since 1970-01-01 00:00:00 UTC, `None` before `init`
+// it advances with the monotonic clock, so it never goes backwards and doesn't jump when the
+// RTC is changed
+pub fn unix_nanos() -> Option<u64> {
+    match BOOT_UNIX_NANOS.load(Ordering::Relaxed) {
+        0 => None,
+        boot => Some(boot + monotonic_now()),
+    }
+}
+
+// the current date and time, `None` before `init`
+pub fn now() -> Option<DateTime> {
+    unix_nanos().map(DateTime::from_unix_nanos)
+}
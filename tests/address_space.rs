@@ -0,0 +1,123 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(p0nd_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use bootloader::{BootInfo, entry_point};
+use core::panic::PanicInfo;
+use p0nd_os::allocator::{self, HEAP_START};
+use p0nd_os::memory::{
+    self, AddressSpace,
+    address_space::{self, USER_SPACE_START},
+};
+use x86_64::VirtAddr;
+use x86_64::structures::paging::{PageTableFlags, Translate};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use p0nd_os::memory::bitmap::BitmapFrameAllocator;
+
+    p0nd_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator =
+        unsafe { BitmapFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    memory::init_kernel_memory(mapper, frame_allocator);
+    allocator::init_heap().expect("heap init failed");
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    p0nd_os::test_panic_handler(info)
+}
+
+const USER_FLAGS: PageTableFlags = PageTableFlags::PRESENT
+    .union(PageTableFlags::WRITABLE)
+    .union(PageTableFlags::USER_ACCESSIBLE);
+
+fn kernel_translate(addr: VirtAddr) -> Option<x86_64::PhysAddr> {
+    memory::KERNEL_MEMORY
+        .lock()
+        .as_ref()
+        .unwrap()
+        .mapper
+        .translate_addr(addr)
+}
+
+#[test_case]
+fn new_space_shares_the_kernel_mappings() {
+    let space = AddressSpace::new().unwrap();
+    let heap_addr = VirtAddr::new(HEAP_START as u64 + 8);
+
+    assert!(!space.is_active());
+    assert_eq!(space.translate(heap_addr), kernel_translate(heap_addr));
+    assert_eq!(space.translate(VirtAddr::new(USER_SPACE_START)), None);
+}
+
+#[test_case]
+fn user_mappings_are_private() {
+    let user_addr = VirtAddr::new(USER_SPACE_START);
+    let mut space = AddressSpace::new().unwrap();
+    let other = AddressSpace::new().unwrap();
+
+    space.map_range(user_addr, 2 * 4096, USER_FLAGS).unwrap();
+    assert!(space.translate(user_addr + 4096u64).is_some());
+    assert_eq!(other.translate(user_addr), None);
+    assert_eq!(kernel_translate(user_addr), None);
+
+    space.unmap_range(user_addr, 2 * 4096);
+    assert_eq!(space.translate(user_addr), None);
+}
+
+#[test_case]
+fn switching_spaces_keeps_the_kernel_running() {
+    let user_addr = VirtAddr::new(USER_SPACE_START + 0x10_0000);
+    let mut space = AddressSpace::new().unwrap();
+    space.map_range(user_addr, 4096, USER_FLAGS).unwrap();
+
+    space.activate();
+    assert!(space.is_active());
+    let value = user_addr.as_mut_ptr::<u64>();
+    unsafe {
+        assert_eq!(value.read_volatile(), 0);
+        value.write_volatile(42);
+        assert_eq!(value.read_volatile(), 42);
+    }
+    // the heap is still reachable from the new table
+    let boxed = alloc::boxed::Box::new(7);
+    assert_eq!(*boxed, 7);
+    drop(boxed);
+
+    address_space::activate_kernel();
+    assert!(!space.is_active());
+    assert!(!memory::translate(user_addr).unwrap().is_mapped());
+}
+
+#[test_case]
+fn dropped_space_frees_its_frames() {
+    let free_before = memory::free_frames();
+
+    let mut space = AddressSpace::new().unwrap();
+    // spread over two level 3 entries so that several page tables are allocated
+    space
+        .map_range(VirtAddr::new(USER_SPACE_START), 3 * 4096, USER_FLAGS)
+        .unwrap();
+    space
+        .map_range(
+            VirtAddr::new(USER_SPACE_START + 0x40_0000_0000),
+            4096,
+            USER_FLAGS,
+        )
+        .unwrap();
+    assert!(memory::free_frames() < free_before);
+
+    drop(space);
+    assert_eq!(memory::free_frames(), free_before);
+}
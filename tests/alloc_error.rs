@@ -0,0 +1,87 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use bootloader::{BootInfo, entry_point};
+use core::fmt::{self, Write};
+use core::panic::PanicInfo;
+use p0nd_os::allocator::{self, HEAP_MAX_SIZE};
+use p0nd_os::{exit_qemu, serial_print, serial_println};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use p0nd_os::memory::{self, bitmap::BitmapFrameAllocator};
+    use x86_64::VirtAddr;
+
+    p0nd_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator =
+        unsafe { BitmapFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    memory::init_kernel_memory(mapper, frame_allocator);
+    allocator::init_heap().expect("heap init failed");
+
+    serial_print!("alloc_error::allocation_past_heap_limit...\t");
+    let vec: Vec<u8> = Vec::with_capacity(HEAP_MAX_SIZE + 1);
+    serial_println!("[allocation did not fail at {:p}]", vec.as_ptr());
+    exit_qemu(p0nd_os::QemuExitCode::Failed);
+
+    loop {}
+}
+
+// the allocation error handler is expected to panic after the heap dump, any other panic fails
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    let mut message = Buffer::new();
+    let mut expected = Buffer::new();
+    let _ = write!(message, "{}", info.message());
+    let _ = write!(
+        expected,
+        "allocation error: Layout {{ size: {},",
+        HEAP_MAX_SIZE + 1
+    );
+
+    if message.as_bytes().starts_with(expected.as_bytes()) {
+        serial_println!("[ok]");
+        exit_qemu(p0nd_os::QemuExitCode::Success);
+    } else {
+        serial_println!("[failed]\n\nError: {}\n", info);
+        exit_qemu(p0nd_os::QemuExitCode::Failed);
+    }
+
+    loop {}
+}
+
+// the panic message is formatted without the heap, which is what ran out
+struct Buffer {
+    bytes: [u8; 128],
+    len: usize,
+}
+
+impl Buffer {
+    fn new() -> Self {
+        Buffer {
+            bytes: [0; 128],
+            len: 0,
+        }
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+}
+
+impl Write for Buffer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let end = self.len + s.len();
+        self.bytes
+            .get_mut(self.len..end)
+            .ok_or(fmt::Error)?
+            .copy_from_slice(s.as_bytes());
+        self.len = end;
+        Ok(())
+    }
+}
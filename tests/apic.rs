@@ -0,0 +1,82 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(p0nd_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{BootInfo, entry_point};
+use core::panic::PanicInfo;
+use p0nd_os::interrupts::apic::{self, TimerDivide, TimerMode};
+use p0nd_os::interrupts::controller::{self, ApicError, Controller};
+use p0nd_os::interrupts::{InterruptIndex, PICS, ioapic};
+use p0nd_os::{allocator, time};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use p0nd_os::memory::{self, bitmap::BitmapFrameAllocator};
+    use x86_64::VirtAddr;
+
+    p0nd_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator =
+        unsafe { BitmapFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    memory::init_kernel_memory(mapper, frame_allocator);
+    allocator::init_heap().expect("heap init failed");
+    p0nd_os::interrupts::enable_apic().expect("the APIC couldn't be enabled");
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    p0nd_os::test_panic_handler(info)
+}
+
+#[test_case]
+fn apic_replaces_the_8259() {
+    assert_eq!(controller::active(), Controller::Apic);
+    assert_eq!(unsafe { PICS.lock().read_masks() }, [0xff, 0xff]);
+    assert_eq!(
+        p0nd_os::interrupts::enable_apic(),
+        Err(ApicError::AlreadyEnabled)
+    );
+}
+
+#[test_case]
+fn isa_irqs_are_routed_through_the_ioapic() {
+    for index in [InterruptIndex::Timer, InterruptIndex::Keyboard] {
+        let gsi = ioapic::isa_irq_to_gsi(index.isa_irq().unwrap());
+        let masked = controller::with_io_apic(|io_apic| io_apic.is_masked(gsi));
+        assert_eq!(masked, Some(false));
+    }
+
+    // the PIT ticks keep coming, now through the IOAPIC and the local APIC
+    let start = time::ticks();
+    time::wait_ticks(3);
+    assert!(time::ticks() >= start + 3);
+}
+
+#[test_case]
+fn local_apic_timer_fires_periodically() {
+    let local_apic = controller::local_apic().unwrap();
+    let frequency = local_apic.measure_timer_frequency(TimerDivide::By16, 5);
+    assert!(frequency > 0);
+
+    // about one interrupt per tick
+    let count = (frequency / u64::from(time::tick_frequency())) as u32;
+    let before = apic::timer_interrupts();
+    local_apic.start_timer(
+        InterruptIndex::LocalApicTimer.as_u8(),
+        TimerMode::Periodic,
+        TimerDivide::By16,
+        count,
+    );
+    time::wait_ticks(10);
+    local_apic.stop_timer();
+
+    let fired = apic::timer_interrupts() - before;
+    assert!((5..=20).contains(&fired), "{} timer interrupts", fired);
+}
@@ -0,0 +1,106 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(p0nd_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{BootInfo, entry_point};
+use core::panic::PanicInfo;
+use core::time::Duration;
+use p0nd_os::time::clocksource::{self, ClockSource};
+use p0nd_os::time::{self, monotonic_now, udelay};
+use p0nd_os::{acpi, allocator};
+use x86_64::instructions::interrupts;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use p0nd_os::memory::{self, bitmap::BitmapFrameAllocator};
+    use x86_64::VirtAddr;
+
+    p0nd_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator =
+        unsafe { BitmapFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    memory::init_kernel_memory(mapper, frame_allocator);
+    allocator::init_heap().expect("heap init failed");
+    clocksource::init();
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    p0nd_os::test_panic_handler(info)
+}
+
+const NANOS_PER_TICK: i64 = 1_000_000_000 / time::DEFAULT_TICK_HZ as i64;
+
+fn high_resolution() -> bool {
+    clocksource::source() != Some(ClockSource::Pit)
+}
+
+#[test_case]
+fn clock_source_is_picked_once() {
+    let source = clocksource::source().unwrap();
+    assert_eq!(clocksource::init(), source);
+    assert_eq!(
+        clocksource::tsc_frequency().is_some(),
+        source == ClockSource::Tsc
+    );
+    if source == ClockSource::Hpet {
+        assert!(acpi::find_table(b"HPET").is_some());
+    }
+}
+
+#[test_case]
+fn acpi_tables_are_found() {
+    let fadt = acpi::find_table(b"FACP").expect("no FADT");
+    assert_eq!(&fadt.signature, b"FACP");
+    assert!(acpi::find_table(b"NONE").is_none());
+}
+
+#[test_case]
+fn monotonic_now_never_goes_backwards() {
+    let mut previous = monotonic_now();
+    for _ in 0..10_000 {
+        let now = monotonic_now();
+        assert!(now >= previous);
+        previous = now;
+    }
+}
+
+#[test_case]
+fn udelay_waits_at_least_the_delay() {
+    let start = monotonic_now();
+    udelay(2_000);
+    assert!(monotonic_now() - start >= 2_000_000);
+
+    // the delay doesn't rely on interrupts
+    let start = monotonic_now();
+    interrupts::without_interrupts(|| udelay(1_000));
+    if high_resolution() {
+        assert!(monotonic_now() - start >= 1_000_000);
+    }
+}
+
+#[test_case]
+fn clock_agrees_with_the_ticks() {
+    let start = monotonic_now();
+    let start_uptime = time::uptime();
+    time::wait_ticks(20);
+    let elapsed = Duration::from_nanos(monotonic_now() - start);
+    let elapsed_uptime = time::uptime() - start_uptime;
+    assert!(elapsed.abs_diff(elapsed_uptime) <= Duration::from_nanos(2 * NANOS_PER_TICK as u64));
+
+    if high_resolution() {
+        let drift = clocksource::tick_drift().expect("no tick was recorded");
+        assert!(
+            drift.abs() < NANOS_PER_TICK,
+            "the ticks drifted {} ns",
+            drift
+        );
+    }
+}
@@ -0,0 +1,144 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(p0nd_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{BootInfo, entry_point};
+use core::panic::PanicInfo;
+use p0nd_os::allocator;
+use p0nd_os::memory::{
+    self, AddressSpace,
+    address_space::{self, USER_SPACE_START},
+    cow::COPY_ON_WRITE,
+};
+use x86_64::structures::paging::{PageTableFlags, PhysFrame};
+use x86_64::{PhysAddr, VirtAddr};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use p0nd_os::memory::bitmap::BitmapFrameAllocator;
+
+    p0nd_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator =
+        unsafe { BitmapFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    memory::init_kernel_memory(mapper, frame_allocator);
+    allocator::init_heap().expect("heap init failed");
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    p0nd_os::test_panic_handler(info)
+}
+
+const USER_FLAGS: PageTableFlags = PageTableFlags::PRESENT
+    .union(PageTableFlags::WRITABLE)
+    .union(PageTableFlags::USER_ACCESSIBLE);
+
+fn ref_count(phys: PhysAddr) -> usize {
+    memory::KERNEL_MEMORY
+        .lock()
+        .as_ref()
+        .unwrap()
+        .frame_allocator
+        .ref_count(PhysFrame::containing_address(phys))
+}
+
+fn flags(addr: VirtAddr) -> PageTableFlags {
+    memory::translate(addr).unwrap().flags().unwrap()
+}
+
+#[test_case]
+fn forked_pages_are_copied_on_write() {
+    let addr = VirtAddr::new(USER_SPACE_START);
+    let value = addr.as_mut_ptr::<u64>();
+
+    let mut parent = AddressSpace::new().unwrap();
+    parent.map_range(addr, 4096, USER_FLAGS).unwrap();
+    parent.activate();
+    unsafe { value.write_volatile(42) };
+
+    let child = parent.fork().unwrap();
+    let shared = parent.translate(addr).unwrap();
+    assert_eq!(child.translate(addr), Some(shared));
+    assert_eq!(ref_count(shared), 2);
+    assert!(flags(addr).contains(COPY_ON_WRITE));
+    assert!(!flags(addr).contains(PageTableFlags::WRITABLE));
+
+    // the child writes first and gets a copy
+    child.activate();
+    unsafe {
+        assert_eq!(value.read_volatile(), 42);
+        value.write_volatile(43);
+        assert_eq!(value.read_volatile(), 43);
+    }
+    let copy = child.translate(addr).unwrap();
+    assert_ne!(copy, shared);
+    assert!(flags(addr).contains(PageTableFlags::WRITABLE));
+    assert_eq!(ref_count(shared), 1);
+
+    // the parent is the last owner and keeps the frame
+    parent.activate();
+    unsafe {
+        assert_eq!(value.read_volatile(), 42);
+        value.write_volatile(44);
+    }
+    assert_eq!(parent.translate(addr), Some(shared));
+    assert!(!flags(addr).contains(COPY_ON_WRITE));
+
+    address_space::activate_kernel();
+}
+
+#[test_case]
+fn zero_pages_share_one_frame() {
+    let start = VirtAddr::new(USER_SPACE_START + 0x20_0000);
+
+    let mut space = AddressSpace::new().unwrap();
+    space.map_zero_range(start, 3 * 4096, USER_FLAGS).unwrap();
+    let zero_frame = space.translate(start).unwrap();
+    assert_eq!(space.translate(start + 2 * 4096u64), Some(zero_frame));
+
+    space.activate();
+    let second = (start + 4096u64).as_mut_ptr::<u64>();
+    unsafe {
+        assert_eq!(second.read_volatile(), 0);
+        second.write_volatile(7);
+        assert_eq!(second.read_volatile(), 7);
+        assert_eq!(start.as_ptr::<u64>().read_volatile(), 0);
+    }
+    address_space::activate_kernel();
+
+    assert_ne!(space.translate(start + 4096u64), Some(zero_frame));
+    assert_eq!(space.translate(start + 2 * 4096u64), Some(zero_frame));
+}
+
+#[test_case]
+fn dropped_spaces_release_shared_frames() {
+    let addr = VirtAddr::new(USER_SPACE_START + 0x40_0000);
+
+    // the zero frame is allocated once and kept, so it is set up before counting
+    let mut first_user = AddressSpace::new().unwrap();
+    first_user.map_zero_range(addr, 4096, USER_FLAGS).unwrap();
+    drop(first_user);
+    let free_before = memory::free_frames();
+
+    let mut parent = AddressSpace::new().unwrap();
+    parent.map_range(addr, 2 * 4096, USER_FLAGS).unwrap();
+    parent
+        .map_zero_range(addr + 2 * 4096u64, 4096, USER_FLAGS)
+        .unwrap();
+    let child = parent.fork().unwrap();
+    let shared = child.translate(addr).unwrap();
+
+    drop(parent);
+    assert_eq!(ref_count(shared), 1);
+    drop(child);
+    assert_eq!(ref_count(shared), 0);
+    assert_eq!(memory::free_frames(), free_before);
+}
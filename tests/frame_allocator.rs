@@ -0,0 +1,122 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(p0nd_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{BootInfo, entry_point};
+use core::panic::PanicInfo;
+use p0nd_os::memory::bitmap::BitmapFrameAllocator;
+use spin::Mutex;
+use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, PhysFrame, Size2MiB};
+
+static FRAME_ALLOCATOR: Mutex<Option<BitmapFrameAllocator>> = Mutex::new(None);
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use x86_64::VirtAddr;
+
+    p0nd_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let frame_allocator =
+        unsafe { BitmapFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    *FRAME_ALLOCATOR.lock() = Some(frame_allocator);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    p0nd_os::test_panic_handler(info)
+}
+
+#[test_case]
+fn allocated_frames_are_unique() {
+    let mut guard = FRAME_ALLOCATOR.lock();
+    let allocator = guard.as_mut().unwrap();
+
+    let first = allocator.allocate_frame().unwrap();
+    let second = allocator.allocate_frame().unwrap();
+
+    assert_ne!(first, second);
+    assert!(!allocator.is_free(first));
+    assert!(!allocator.is_free(second));
+
+    unsafe {
+        allocator.deallocate_frame(first);
+        allocator.deallocate_frame(second);
+    }
+}
+
+#[test_case]
+fn freed_frame_is_reused() {
+    let mut guard = FRAME_ALLOCATOR.lock();
+    let allocator = guard.as_mut().unwrap();
+    let free_before = allocator.free_frames();
+
+    let frame = allocator.allocate_frame().unwrap();
+    assert_eq!(allocator.free_frames(), free_before - 1);
+
+    unsafe { allocator.deallocate_frame(frame) };
+    assert!(allocator.is_free(frame));
+    assert_eq!(allocator.free_frames(), free_before);
+
+    // the lowest free frame is always handed out first
+    assert_eq!(allocator.allocate_frame(), Some(frame));
+    unsafe { allocator.deallocate_frame(frame) };
+}
+
+#[test_case]
+fn contiguous_allocation() {
+    let mut guard = FRAME_ALLOCATOR.lock();
+    let allocator = guard.as_mut().unwrap();
+    let free_before = allocator.free_frames();
+
+    let range = allocator.allocate_contiguous(16, 16).unwrap();
+    assert_eq!(range.end - range.start, 16);
+    assert_eq!(range.start.start_address().as_u64() % (16 * 4096), 0);
+    assert_eq!(allocator.free_frames(), free_before - 16);
+    for frame in range {
+        assert!(!allocator.is_free(frame));
+    }
+
+    unsafe { allocator.deallocate_contiguous(range) };
+    assert_eq!(allocator.free_frames(), free_before);
+}
+
+#[test_case]
+fn huge_frame_allocation() {
+    let mut guard = FRAME_ALLOCATOR.lock();
+    let allocator = guard.as_mut().unwrap();
+    let free_before = allocator.free_frames();
+
+    let frame: PhysFrame<Size2MiB> = allocator.allocate_frame().unwrap();
+    assert_eq!(frame.start_address().as_u64() % (2 * 1024 * 1024), 0);
+    assert_eq!(allocator.free_frames(), free_before - 512);
+
+    unsafe { allocator.deallocate_frame(frame) };
+    assert_eq!(allocator.free_frames(), free_before);
+}
+
+#[test_case]
+fn shared_frame_is_freed_by_its_last_owner() {
+    let mut guard = FRAME_ALLOCATOR.lock();
+    let allocator = guard.as_mut().unwrap();
+
+    let frame: PhysFrame = allocator.allocate_frame().unwrap();
+    assert_eq!(allocator.ref_count(frame), 1);
+    allocator.share_frame(frame);
+    allocator.share_frame(frame);
+    assert_eq!(allocator.ref_count(frame), 3);
+
+    unsafe { allocator.deallocate_frame(frame) };
+    unsafe { allocator.deallocate_frame(frame) };
+    assert_eq!(allocator.ref_count(frame), 1);
+    assert!(!allocator.is_free(frame));
+
+    unsafe { allocator.deallocate_frame(frame) };
+    assert_eq!(allocator.ref_count(frame), 0);
+    assert!(allocator.is_free(frame));
+}
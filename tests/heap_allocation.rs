@@ -6,23 +6,25 @@
 
 extern crate alloc;
 
-use alloc::{boxed::Box, vec::Vec};
+use alloc::{boxed::Box, vec, vec::Vec};
 use bootloader::{BootInfo, entry_point};
 use core::panic::PanicInfo;
-use p0nd_os::allocator::HEAP_SIZE;
+use p0nd_os::AllowLeaks;
+use p0nd_os::allocator::{self, HEAP_SIZE};
 
 entry_point!(main);
 
 fn main(boot_info: &'static BootInfo) -> ! {
-    use p0nd_os::allocator;
-    use p0nd_os::memory::{self, BootInfoFrameAllocator};
+    use p0nd_os::memory::{self, bitmap::BitmapFrameAllocator};
     use x86_64::VirtAddr;
 
     p0nd_os::init();
     let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
-    let mut mapper = unsafe { memory::init(phys_mem_offset) };
-    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
-    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap init failed");
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator =
+        unsafe { BitmapFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    memory::init_kernel_memory(mapper, frame_allocator);
+    allocator::init_heap().expect("heap init failed");
 
     test_main();
     loop {}
@@ -71,3 +73,39 @@ fn many_boxes_long_lived() {
     }
     assert_eq!(*long_lived, 1); // new
 }
+
+#[test_case]
+fn heap_stats_track_allocations() {
+    let before = allocator::heap_stats();
+    let value = Box::new([0u8; 64]);
+    let during = allocator::heap_stats();
+    assert_eq!(during.allocated, before.allocated + 64);
+    assert!(during.high_water_mark >= during.allocated);
+
+    drop(value);
+    assert_eq!(allocator::heap_stats().allocated, before.allocated);
+    assert_eq!(allocator::heap_stats().allocations, before.allocations);
+}
+
+#[test_case]
+fn heap_grows_on_demand() {
+    let large = vec![1u8; 2 * HEAP_SIZE];
+
+    assert!(allocator::heap_stats().size > HEAP_SIZE);
+    assert_eq!(
+        large.iter().map(|&b| b as usize).sum::<usize>(),
+        2 * HEAP_SIZE
+    );
+}
+
+// leaks on purpose, so it is kept out of the leak report
+#[test_case]
+const LEAKED_BOX_STAYS_VALID: AllowLeaks = AllowLeaks::new(&leaked_box_stays_valid);
+
+fn leaked_box_stays_valid() {
+    let before = allocator::heap_stats();
+    let leaked: &'static mut u64 = Box::leak(Box::new(42));
+
+    assert_eq!(*leaked, 42);
+    assert_eq!(allocator::heap_stats().allocations, before.allocations + 1);
+}
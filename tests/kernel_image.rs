@@ -0,0 +1,62 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(p0nd_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{BootInfo, entry_point};
+use core::panic::PanicInfo;
+use p0nd_os::memory::{self, protect};
+use x86_64::VirtAddr;
+use x86_64::structures::paging::PageTableFlags;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use p0nd_os::memory::bitmap::BitmapFrameAllocator;
+
+    p0nd_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator =
+        unsafe { BitmapFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    memory::init_kernel_memory(mapper, frame_allocator);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    p0nd_os::test_panic_handler(info)
+}
+
+// lives in `.data`, a zeroed static would end up in `.bss`
+static mut DATA: u64 = 0x1234;
+
+fn code() {}
+
+fn flags_of(addr: VirtAddr) -> PageTableFlags {
+    memory::translate(addr)
+        .and_then(|translation| translation.flags())
+        .expect("the kernel image is mapped")
+}
+
+#[test_case]
+fn kernel_image_is_remapped() {
+    assert!(protect::kernel_image_hardened());
+}
+
+#[test_case]
+fn text_is_read_only_and_executable() {
+    let flags = flags_of(VirtAddr::from_ptr(code as *const ()));
+    assert!(!flags.contains(PageTableFlags::WRITABLE), "{:?}", flags);
+    assert!(!flags.contains(PageTableFlags::NO_EXECUTE), "{:?}", flags);
+}
+
+#[test_case]
+fn data_is_writable_and_not_executable() {
+    let flags = flags_of(VirtAddr::from_ptr(&raw const DATA));
+    assert!(flags.contains(PageTableFlags::WRITABLE), "{:?}", flags);
+    assert!(flags.contains(PageTableFlags::NO_EXECUTE), "{:?}", flags);
+}
@@ -0,0 +1,86 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(p0nd_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{BootInfo, entry_point};
+use core::panic::PanicInfo;
+use p0nd_os::memory::{self, stack, vma};
+use x86_64::VirtAddr;
+use x86_64::structures::paging::Translate;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use p0nd_os::memory::bitmap::BitmapFrameAllocator;
+
+    p0nd_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator =
+        unsafe { BitmapFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    memory::init_kernel_memory(mapper, frame_allocator);
+    p0nd_os::gdt::init_ist_stacks();
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    p0nd_os::test_panic_handler(info)
+}
+
+fn is_mapped(addr: VirtAddr) -> bool {
+    memory::KERNEL_MEMORY
+        .lock()
+        .as_ref()
+        .unwrap()
+        .mapper
+        .translate_addr(addr)
+        .is_some()
+}
+
+#[test_case]
+fn stack_is_mapped_below_a_guard_page() {
+    let stack = stack::allocate_kernel_stack(4).unwrap();
+    assert_eq!(stack.top() - stack.bottom(), 4 * 4096);
+
+    let top = (stack.top() - 8u64).as_mut_ptr::<u64>();
+    let bottom = stack.bottom().as_mut_ptr::<u64>();
+    unsafe {
+        top.write_volatile(1);
+        bottom.write_volatile(2);
+        assert_eq!(top.read_volatile() + bottom.read_volatile(), 3);
+    }
+
+    assert!(!is_mapped(stack.guard_page().start_address()));
+}
+
+#[test_case]
+fn dropped_stack_is_unmapped() {
+    let free_before = memory::free_frames();
+
+    let stack = stack::allocate_kernel_stack(stack::MAX_STACK_PAGES).unwrap();
+    let bottom = stack.bottom();
+    assert!(is_mapped(bottom));
+
+    drop(stack);
+    assert!(!is_mapped(bottom));
+    assert_eq!(memory::free_frames(), free_before);
+
+    // the slot is free again
+    let stack = stack::allocate_kernel_stack(stack::MAX_STACK_PAGES).unwrap();
+    assert_eq!(stack.bottom(), bottom);
+}
+
+#[test_case]
+fn stacks_share_one_region() {
+    let first = stack::allocate_kernel_stack(1).unwrap();
+    let second = stack::allocate_kernel_stack(1).unwrap();
+
+    let region = vma::find(first.guard_page().start_address()).unwrap();
+    assert_eq!(region.name, "kernel stacks");
+    assert!(region.contains(second.bottom()));
+}
@@ -0,0 +1,73 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(p0nd_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{BootInfo, entry_point};
+use core::panic::PanicInfo;
+use p0nd_os::memory::{self, vma};
+use x86_64::structures::paging::{PageTableFlags, Translate, mapper::TranslateResult};
+use x86_64::{PhysAddr, VirtAddr};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use p0nd_os::memory::bitmap::BitmapFrameAllocator;
+
+    p0nd_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator =
+        unsafe { BitmapFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    memory::init_kernel_memory(mapper, frame_allocator);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    p0nd_os::test_panic_handler(info)
+}
+
+// the VGA text buffer stands in for device memory
+const VGA_BUFFER: u64 = 0xb8000;
+
+fn translate(addr: VirtAddr) -> TranslateResult {
+    memory::KERNEL_MEMORY
+        .lock()
+        .as_ref()
+        .unwrap()
+        .mapper
+        .translate(addr)
+}
+
+#[test_case]
+fn mmio_is_mapped_uncached() {
+    // the last character of the first line
+    let region = memory::map_mmio(PhysAddr::new(VGA_BUFFER + 158), 2).unwrap();
+    assert_eq!(region.virt_addr().as_u64() % 4096, 158);
+
+    match translate(region.virt_addr()) {
+        TranslateResult::Mapped { frame, flags, .. } => {
+            assert_eq!(frame.start_address().as_u64(), VGA_BUFFER);
+            assert!(flags.contains(PageTableFlags::NO_CACHE | PageTableFlags::WRITE_THROUGH));
+        }
+        _ => panic!("MMIO region is not mapped"),
+    }
+
+    region.write::<u16>(0, 0x0f21);
+    assert_eq!(region.read::<u16>(0), 0x0f21);
+}
+
+#[test_case]
+fn dropped_region_is_unmapped() {
+    let region = memory::map_mmio(PhysAddr::new(VGA_BUFFER), 4096).unwrap();
+    let addr = region.virt_addr();
+    assert_eq!(vma::find(addr).unwrap().name, "mmio");
+
+    drop(region);
+    assert!(matches!(translate(addr), TranslateResult::NotMapped));
+    assert!(vma::find(addr).is_none());
+}
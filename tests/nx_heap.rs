@@ -0,0 +1,57 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use bootloader::{BootInfo, entry_point};
+use core::panic::PanicInfo;
+use p0nd_os::allocator;
+use p0nd_os::{QemuExitCode, exit_qemu, serial_print, serial_println};
+use spin::Mutex;
+use x86_64::VirtAddr;
+use x86_64::registers::control::Cr2;
+
+// the address the test jumps to, the page fault has to report it
+static CODE_ADDR: Mutex<Option<VirtAddr>> = Mutex::new(None);
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use p0nd_os::memory::{self, bitmap::BitmapFrameAllocator};
+
+    p0nd_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator =
+        unsafe { BitmapFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    memory::init_kernel_memory(mapper, frame_allocator);
+    allocator::init_heap().expect("heap init failed");
+
+    serial_print!("nx_heap::executing_heap_memory_faults...\t");
+    // a single `ret`, which would return right away if the heap were executable
+    let code = Box::leak(Box::new([0xc3u8; 16]));
+    *CODE_ADDR.lock() = Some(VirtAddr::from_ptr(code.as_ptr()));
+
+    let function: extern "C" fn() = unsafe { core::mem::transmute(code.as_ptr()) };
+    function();
+
+    serial_println!("[heap memory was executed]");
+    exit_qemu(QemuExitCode::Failed);
+
+    loop {}
+}
+
+// the page fault handler panics on the instruction fetch from the heap
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    if CODE_ADDR.lock().is_some_and(|addr| addr == Cr2::read()) {
+        serial_println!("[ok]");
+        exit_qemu(QemuExitCode::Success);
+    } else {
+        serial_println!("[failed]\n\nError: {}\n", info);
+        exit_qemu(QemuExitCode::Failed);
+    }
+
+    loop {}
+}
@@ -0,0 +1,79 @@
+#![no_std]
+#![no_main]
+
+use bootloader::{BootInfo, entry_point};
+use core::fmt::{self, Write};
+use core::panic::PanicInfo;
+use p0nd_os::{QemuExitCode, exit_qemu, serial_print, serial_println};
+
+const UNMAPPED_ADDR: u64 = 0x_dead_beef_0000;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use p0nd_os::memory::{self, bitmap::BitmapFrameAllocator};
+    use x86_64::VirtAddr;
+
+    p0nd_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator =
+        unsafe { BitmapFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    memory::init_kernel_memory(mapper, frame_allocator);
+
+    serial_print!("page_fault::unmapped_write_panics...\t");
+    unsafe { (UNMAPPED_ADDR as *mut u64).write_volatile(42) };
+    serial_println!("[page fault not raised]");
+    exit_qemu(QemuExitCode::Failed);
+
+    loop {}
+}
+
+// the page fault handler is expected to print its report and panic
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    let mut message = MessageBuffer::new();
+    let _ = write!(message, "{}", info.message());
+
+    if message
+        .as_str()
+        .starts_with("unhandled page fault at 0xdeadbeef0000: kernel write to a not-present page")
+    {
+        serial_println!("[ok]");
+        exit_qemu(QemuExitCode::Success);
+    } else {
+        serial_println!("[failed]\n\nError: {}\n", info);
+        exit_qemu(QemuExitCode::Failed);
+    }
+
+    loop {}
+}
+
+// keeps the first bytes of the panic message, there is no heap to format into
+struct MessageBuffer {
+    bytes: [u8; 128],
+    len: usize,
+}
+
+impl MessageBuffer {
+    fn new() -> Self {
+        MessageBuffer {
+            bytes: [0; 128],
+            len: 0,
+        }
+    }
+
+    fn as_str(&self) -> &str {
+        core::str::from_utf8(&self.bytes[..self.len]).unwrap_or("")
+    }
+}
+
+impl Write for MessageBuffer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let len = s.len().min(self.bytes.len() - self.len);
+        self.bytes[self.len..self.len + len].copy_from_slice(&s.as_bytes()[..len]);
+        self.len += len;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,114 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(p0nd_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{BootInfo, entry_point};
+use core::panic::PanicInfo;
+use p0nd_os::allocator::{self, HEAP_START};
+use p0nd_os::memory::{
+    self,
+    vma::{self, VmaBacking},
+    walk,
+};
+use x86_64::VirtAddr;
+use x86_64::structures::paging::{PageTableFlags, Translate, page_table::PageTableLevel};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use p0nd_os::memory::bitmap::BitmapFrameAllocator;
+
+    p0nd_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator =
+        unsafe { BitmapFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    memory::init_kernel_memory(mapper, frame_allocator);
+    allocator::init_heap().expect("heap init failed");
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    p0nd_os::test_panic_handler(info)
+}
+
+const FLAGS: PageTableFlags = PageTableFlags::PRESENT.union(PageTableFlags::WRITABLE);
+
+#[test_case]
+fn heap_address_translates_like_the_mapper() {
+    let addr = VirtAddr::new(HEAP_START as u64 + 0x123);
+    let translation = memory::translate(addr).unwrap();
+    let expected = memory::KERNEL_MEMORY
+        .lock()
+        .as_ref()
+        .unwrap()
+        .mapper
+        .translate_addr(addr);
+
+    assert!(translation.is_mapped());
+    assert_eq!(translation.phys_addr(), expected);
+    assert!(translation.flags().unwrap().contains(FLAGS));
+    assert_eq!(
+        translation.steps().count(),
+        5 - translation.level() as usize
+    );
+}
+
+#[test_case]
+fn unmapped_address_reports_where_the_walk_ended() {
+    let translation = memory::translate(VirtAddr::new(0x_dead_beef_0000)).unwrap();
+
+    assert!(!translation.is_mapped());
+    assert_eq!(translation.phys_addr(), None);
+    assert_eq!(translation.page_size(), None);
+    let last_step = translation.steps().last().unwrap();
+    assert_eq!(last_step.level, translation.level());
+    assert!(!last_step.flags.contains(PageTableFlags::PRESENT));
+}
+
+#[test_case]
+fn huge_page_ends_the_walk_at_level_2() {
+    let region =
+        vma::reserve("walk buffer", 2 * 1024 * 1024, FLAGS, VmaBacking::Anonymous).unwrap();
+    vma::map(region.start).unwrap();
+
+    let translation = memory::translate(region.start + 0x1234u64).unwrap();
+    assert_eq!(translation.level(), PageTableLevel::Two);
+    assert_eq!(translation.page_size(), Some(2 * 1024 * 1024));
+    assert_eq!(
+        translation.phys_addr().unwrap().as_u64() % (2 * 1024 * 1024),
+        0x1234
+    );
+
+    vma::release(region.start).unwrap();
+    assert!(!memory::translate(region.start).unwrap().is_mapped());
+}
+
+#[test_case]
+fn mappings_are_merged_and_cover_the_heap() {
+    let heap_start = VirtAddr::new(HEAP_START as u64);
+    let mut heap_mapped = false;
+    let mut previous: Option<walk::Mapping> = None;
+
+    walk::for_each_mapping(|mapping| {
+        if mapping.start <= heap_start && heap_start < mapping.start + mapping.size {
+            heap_mapped = mapping.flags.contains(FLAGS);
+        }
+
+        // neighbouring runs would have been merged unless they differ in something
+        if let Some(previous) = previous {
+            assert!(previous.start < mapping.start);
+            let adjacent = previous.start + previous.size == mapping.start
+                && previous.phys + previous.size == mapping.phys;
+            assert!(!adjacent || previous.flags != mapping.flags);
+        }
+        previous = Some(mapping);
+    });
+
+    assert!(heap_mapped);
+}
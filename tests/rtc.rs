@@ -0,0 +1,73 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(p0nd_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{BootInfo, entry_point};
+use core::panic::PanicInfo;
+use p0nd_os::allocator;
+use p0nd_os::time::{self, DateTime, clocksource, rtc, wall_clock};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use p0nd_os::memory::{self, bitmap::BitmapFrameAllocator};
+    use x86_64::VirtAddr;
+
+    p0nd_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator =
+        unsafe { BitmapFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    memory::init_kernel_memory(mapper, frame_allocator);
+    allocator::init_heap().expect("heap init failed");
+    clocksource::init();
+    assert_eq!(wall_clock::now(), None);
+    let boot_time = wall_clock::init();
+    assert!(
+        boot_time > DateTime::UNIX_EPOCH,
+        "the RTC holds no valid date"
+    );
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    p0nd_os::test_panic_handler(info)
+}
+
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+#[test_case]
+fn rtc_reads_a_valid_date() {
+    let date_time = rtc::read().expect("the RTC holds no valid date");
+    assert!(date_time.is_valid(), "{:?}", date_time);
+    assert!(date_time.year >= 2024, "{}", date_time);
+    assert!(date_time > DateTime::UNIX_EPOCH);
+}
+
+#[test_case]
+fn wall_clock_follows_the_rtc() {
+    let now = wall_clock::now().unwrap();
+    let rtc_seconds = rtc::read().unwrap().unix_seconds();
+    assert!(now.unix_seconds().abs_diff(rtc_seconds) <= 1, "{}", now);
+}
+
+#[test_case]
+fn wall_clock_advances_with_the_ticks() {
+    let start = wall_clock::unix_nanos().unwrap();
+    time::wait_ticks(time::DEFAULT_TICK_HZ as u64 / 5);
+    let elapsed = wall_clock::unix_nanos().unwrap() - start;
+
+    // a fifth of a second, give or take two ticks
+    let tick = NANOS_PER_SEC / time::DEFAULT_TICK_HZ as u64;
+    assert!(
+        elapsed.abs_diff(NANOS_PER_SEC / 5) <= 2 * tick,
+        "{} ns",
+        elapsed
+    );
+    assert!(wall_clock::now().unwrap() >= DateTime::from_unix_nanos(start));
+}
@@ -0,0 +1,131 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(p0nd_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{BootInfo, entry_point};
+use core::future;
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::time::Duration;
+use p0nd_os::task::{self, Elapsed, timer};
+use p0nd_os::{allocator, join, select, time};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use p0nd_os::memory::{self, bitmap::BitmapFrameAllocator};
+    use x86_64::VirtAddr;
+
+    p0nd_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator =
+        unsafe { BitmapFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    memory::init_kernel_memory(mapper, frame_allocator);
+    allocator::init_heap().expect("heap init failed");
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    p0nd_os::test_panic_handler(info)
+}
+
+#[test_case]
+fn timeout_gives_up_after_the_duration() {
+    let duration = Duration::from_millis(30);
+    let start = time::uptime();
+    let result = task::block_on(task::timeout(duration, future::pending::<()>()));
+    assert_eq!(result, Err(Elapsed));
+    assert!(time::uptime() - start >= duration);
+    assert_eq!(timer::pending_timers(), 0);
+}
+
+#[test_case]
+fn timeout_returns_the_output_in_time() {
+    let result = task::block_on(task::timeout(Duration::from_secs(10), async {
+        timer::sleep(Duration::from_millis(10)).await;
+        42
+    }));
+    assert_eq!(result, Ok(42));
+    assert_eq!(timer::pending_timers(), 0);
+}
+
+static DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+struct CountDrop;
+
+impl Drop for CountDrop {
+    fn drop(&mut self) {
+        DROPPED.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+#[test_case]
+fn select_runs_the_first_branch_and_drops_the_others() {
+    DROPPED.store(0, Ordering::SeqCst);
+
+    let winner = task::block_on(async {
+        select! {
+            _ = async {
+                let _guard = CountDrop;
+                timer::sleep(Duration::from_secs(10)).await;
+            } => 1,
+            () = timer::sleep(Duration::from_millis(10)) => {
+                // the losing branch is gone before the handler runs
+                assert_eq!(DROPPED.load(Ordering::SeqCst), 1);
+                assert_eq!(timer::pending_timers(), 0);
+                2
+            },
+        }
+    });
+    assert_eq!(winner, 2);
+}
+
+#[test_case]
+fn select_prefers_the_first_ready_branch() {
+    let branch = task::block_on(async {
+        select! {
+            a = async { 1 } => a,
+            b = async { 2 } => b,
+        }
+    });
+    assert_eq!(branch, 1);
+}
+
+#[test_case]
+fn join_waits_for_every_future() {
+    let start = time::uptime();
+    let (a, b, ()) = task::block_on(async {
+        join!(
+            async {
+                timer::sleep(Duration::from_millis(40)).await;
+                "slow"
+            },
+            async {
+                timer::sleep(Duration::from_millis(10)).await;
+                7
+            },
+            timer::sleep(Duration::ZERO),
+        )
+    });
+    assert_eq!((a, b), ("slow", 7));
+    assert!(time::uptime() - start >= Duration::from_millis(40));
+    assert_eq!(timer::pending_timers(), 0);
+}
+
+#[test_case]
+fn timeout_cancels_a_select() {
+    let result = task::block_on(task::timeout(Duration::from_millis(20), async {
+        select! {
+            () = future::pending() => (),
+            () = timer::sleep(Duration::from_secs(10)) => (),
+        }
+    }));
+    assert_eq!(result, Err(Elapsed));
+    assert_eq!(timer::pending_timers(), 0);
+}
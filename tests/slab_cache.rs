@@ -0,0 +1,95 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(p0nd_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{BootInfo, entry_point};
+use core::panic::PanicInfo;
+use p0nd_os::allocator::slab::{SlabBox, SlabCache};
+use p0nd_os::memory;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use p0nd_os::memory::bitmap::BitmapFrameAllocator;
+    use x86_64::VirtAddr;
+
+    p0nd_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator =
+        unsafe { BitmapFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    memory::init_kernel_memory(mapper, frame_allocator);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    p0nd_os::test_panic_handler(info)
+}
+
+#[derive(Debug, PartialEq)]
+struct Point {
+    x: u64,
+    y: u64,
+}
+
+static POINT_CACHE: SlabCache<Point> =
+    SlabCache::with_constructor("point", || Point { x: 1, y: 2 });
+static BUFFER_CACHE: SlabCache<[u8; 1500]> = SlabCache::new("buffer");
+
+#[test_case]
+fn objects_keep_their_values() {
+    let mut first = POINT_CACHE.alloc(Point { x: 3, y: 4 }).unwrap();
+    let second = POINT_CACHE.alloc_constructed().unwrap();
+
+    first.x = 5;
+    assert_eq!(*first, Point { x: 5, y: 4 });
+    assert_eq!(*second, Point { x: 1, y: 2 });
+
+    let stats = POINT_CACHE.stats();
+    assert_eq!(stats.name, "point");
+    assert_eq!(stats.objects_in_use, 2);
+    assert_eq!(stats.slabs, 1);
+
+    drop(first);
+    drop(second);
+    assert_eq!(POINT_CACHE.stats().objects_in_use, 0);
+    assert_eq!(POINT_CACHE.stats().frees, 2);
+}
+
+#[test_case]
+fn freed_slot_is_reused() {
+    let first = POINT_CACHE.alloc_constructed().unwrap();
+    let addr = &*first as *const Point as usize;
+    drop(first);
+
+    let second = POINT_CACHE.alloc_constructed().unwrap();
+    assert_eq!(&*second as *const Point as usize, addr);
+}
+
+#[test_case]
+fn empty_slabs_return_to_frame_allocator() {
+    let free_before = memory::free_frames();
+    let per_slab = BUFFER_CACHE.stats().objects_per_slab;
+
+    // one object more than a slab holds, so freeing them all leaves two empty slabs
+    let buffers: [Option<SlabBox<[u8; 1500]>>; 3] =
+        core::array::from_fn(|_| BUFFER_CACHE.alloc([0xab; 1500]));
+    assert!(buffers.len() > per_slab);
+    assert!(buffers.iter().all(|buffer| buffer.is_some()));
+    assert_eq!(BUFFER_CACHE.stats().slabs, 2);
+    assert!(memory::free_frames() < free_before);
+
+    drop(buffers);
+
+    // the second empty slab went back to the frame allocator right away
+    let stats = BUFFER_CACHE.stats();
+    assert_eq!(stats.slabs, 1);
+    assert_eq!(stats.objects_in_use, 0);
+    assert_eq!(stats.high_water_mark, 3);
+    assert_eq!(memory::free_frames(), free_before - 1);
+}
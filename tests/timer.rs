@@ -0,0 +1,100 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(p0nd_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{BootInfo, entry_point};
+use core::future::Future;
+use core::panic::PanicInfo;
+use core::pin::pin;
+use core::task::{Context, Waker};
+use core::time::Duration;
+use futures_util::StreamExt;
+use p0nd_os::allocator;
+use p0nd_os::task::{
+    self,
+    timer::{self, Interval},
+};
+use p0nd_os::time;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use p0nd_os::memory::{self, bitmap::BitmapFrameAllocator};
+    use x86_64::VirtAddr;
+
+    p0nd_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator =
+        unsafe { BitmapFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    memory::init_kernel_memory(mapper, frame_allocator);
+    allocator::init_heap().expect("heap init failed");
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    p0nd_os::test_panic_handler(info)
+}
+
+#[test_case]
+fn ticks_advance() {
+    let start = time::ticks();
+    while time::ticks() < start + 2 {
+        x86_64::instructions::hlt();
+    }
+    assert!(time::uptime() > Duration::ZERO);
+}
+
+#[test_case]
+fn tick_frequency_is_programmed() {
+    assert_eq!(time::tick_frequency(), time::DEFAULT_TICK_HZ);
+    assert_eq!(time::duration_to_ticks(Duration::from_secs(1)), 100);
+    assert_eq!(time::duration_to_ticks(Duration::from_millis(1)), 1);
+}
+
+#[test_case]
+fn sleep_waits_at_least_the_duration() {
+    let duration = Duration::from_millis(50);
+    let start = time::uptime();
+    task::block_on(timer::sleep(duration));
+    assert!(time::uptime() - start >= duration);
+    assert_eq!(timer::pending_timers(), 0);
+}
+
+#[test_case]
+fn zero_sleep_is_ready_immediately() {
+    task::block_on(timer::sleep(Duration::ZERO));
+    assert_eq!(timer::pending_timers(), 0);
+}
+
+#[test_case]
+fn interval_fires_once_per_period() {
+    let period = Duration::from_millis(20);
+    let start = time::uptime();
+    let mut interval = Interval::new(period);
+
+    for _ in 0..5 {
+        task::block_on(interval.next()).unwrap();
+    }
+    assert!(time::uptime() - start >= period * 5);
+
+    drop(interval);
+    assert_eq!(timer::pending_timers(), 0);
+}
+
+#[test_case]
+fn dropped_sleep_cancels_its_timer() {
+    let mut cx = Context::from_waker(Waker::noop());
+
+    {
+        let mut sleep = pin!(timer::sleep(Duration::from_secs(10)));
+        assert!(sleep.as_mut().poll(&mut cx).is_pending());
+        assert_eq!(timer::pending_timers(), 1);
+    }
+    assert_eq!(timer::pending_timers(), 0);
+}
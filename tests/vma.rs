@@ -0,0 +1,154 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(p0nd_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{BootInfo, entry_point};
+use core::panic::PanicInfo;
+use p0nd_os::allocator::{self, HEAP_START};
+use p0nd_os::memory::{
+    self,
+    vma::{self, VmaBacking, VmaError},
+};
+use x86_64::VirtAddr;
+use x86_64::structures::paging::{
+    PageTableFlags, Translate,
+    mapper::{MappedFrame, TranslateResult},
+};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use p0nd_os::memory::bitmap::BitmapFrameAllocator;
+
+    p0nd_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator =
+        unsafe { BitmapFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    memory::init_kernel_memory(mapper, frame_allocator);
+    allocator::init_heap().expect("heap init failed");
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    p0nd_os::test_panic_handler(info)
+}
+
+const FLAGS: PageTableFlags = PageTableFlags::PRESENT.union(PageTableFlags::WRITABLE);
+
+fn is_mapped(addr: VirtAddr) -> bool {
+    memory::KERNEL_MEMORY
+        .lock()
+        .as_ref()
+        .unwrap()
+        .mapper
+        .translate_addr(addr)
+        .is_some()
+}
+
+#[test_case]
+fn heap_is_a_region() {
+    let heap = vma::find(VirtAddr::new(HEAP_START as u64 + 8)).unwrap();
+    assert_eq!(heap.name, "kernel heap");
+    assert_eq!(heap.backing, VmaBacking::External);
+}
+
+#[test_case]
+fn region_is_mapped_and_released() {
+    let free_before = memory::free_frames();
+    let region = vma::reserve("test buffer", 3 * 4096 - 100, FLAGS, VmaBacking::Anonymous).unwrap();
+    assert_eq!(region.size, 3 * 4096);
+    assert!(!is_mapped(region.start));
+
+    vma::map(region.start).unwrap();
+    let last = (region.end() - 8u64).as_mut_ptr::<u64>();
+    unsafe {
+        last.write_volatile(42);
+        assert_eq!(last.read_volatile(), 42);
+    }
+    assert_eq!(vma::find(region.end() - 1u64).unwrap().name, "test buffer");
+    assert!(vma::find(region.end()).is_none());
+
+    vma::release(region.start).unwrap();
+    assert!(!is_mapped(region.start));
+    assert!(vma::find(region.start).is_none());
+    assert_eq!(memory::free_frames(), free_before);
+}
+
+#[test_case]
+fn regions_do_not_overlap() {
+    let first = vma::reserve("first", 4096, FLAGS, VmaBacking::Anonymous).unwrap();
+    let second = vma::reserve("second", 4096, FLAGS, VmaBacking::Anonymous).unwrap();
+    // an unmapped page is left between neighbouring regions
+    assert!(second.start >= first.end() + 4096u64 || first.start >= second.end() + 4096u64);
+
+    let overlapping = vma::reserve_at(
+        "overlapping",
+        first.start,
+        4096,
+        FLAGS,
+        VmaBacking::Anonymous,
+    );
+    assert_eq!(overlapping.unwrap_err(), VmaError::Overlap);
+
+    vma::release(first.start).unwrap();
+    vma::release(second.start).unwrap();
+    assert_eq!(vma::release(first.start).unwrap_err(), VmaError::NotFound);
+}
+
+#[test_case]
+fn large_region_uses_huge_pages() {
+    let free_before = memory::free_frames();
+    let region = vma::reserve(
+        "large buffer",
+        4 * 1024 * 1024,
+        FLAGS,
+        VmaBacking::Anonymous,
+    )
+    .unwrap();
+    assert_eq!(region.start.as_u64() % (2 * 1024 * 1024), 0);
+
+    vma::map(region.start).unwrap();
+    let translated = memory::KERNEL_MEMORY
+        .lock()
+        .as_ref()
+        .unwrap()
+        .mapper
+        .translate(region.start + 4096u64);
+    assert!(matches!(
+        translated,
+        TranslateResult::Mapped {
+            frame: MappedFrame::Size2MiB(_),
+            ..
+        }
+    ));
+
+    vma::release(region.start).unwrap();
+    assert!(!is_mapped(region.start));
+    assert_eq!(memory::free_frames(), free_before);
+}
+
+#[test_case]
+fn lazy_region_is_mapped_on_first_access() {
+    let region = vma::reserve("lazy buffer", 16 * 4096, FLAGS, VmaBacking::Lazy).unwrap();
+    assert!(!is_mapped(region.start));
+
+    let second_page = (region.start + 4096u64).as_mut_ptr::<u64>();
+    unsafe {
+        assert_eq!(second_page.read_volatile(), 0);
+        second_page.write_volatile(7);
+        assert_eq!(second_page.read_volatile(), 7);
+    }
+    assert!(is_mapped(region.start + 4096u64));
+    assert!(!is_mapped(region.start));
+
+    let free_before_release = memory::free_frames();
+    vma::release(region.start).unwrap();
+    assert!(!is_mapped(region.start + 4096u64));
+    assert_eq!(memory::free_frames(), free_before_release + 1);
+}
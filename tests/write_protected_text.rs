@@ -0,0 +1,46 @@
+#![no_std]
+#![no_main]
+
+use bootloader::{BootInfo, entry_point};
+use core::panic::PanicInfo;
+use p0nd_os::{QemuExitCode, exit_qemu, serial_print, serial_println};
+use x86_64::VirtAddr;
+use x86_64::registers::control::Cr2;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use p0nd_os::memory::{self, bitmap::BitmapFrameAllocator};
+
+    p0nd_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator =
+        unsafe { BitmapFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    memory::init_kernel_memory(mapper, frame_allocator);
+
+    serial_print!("write_protected_text::writing_code_faults...\t");
+    let code = patched_function as *mut u8;
+    unsafe { code.write_volatile(0xc3) };
+
+    serial_println!("[kernel code was overwritten]");
+    exit_qemu(QemuExitCode::Failed);
+
+    loop {}
+}
+
+fn patched_function() {}
+
+// the page fault handler panics on the write to `.text`
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    if Cr2::read() == VirtAddr::from_ptr(patched_function as *const ()) {
+        serial_println!("[ok]");
+        exit_qemu(QemuExitCode::Success);
+    } else {
+        serial_println!("[failed]\n\nError: {}\n", info);
+        exit_qemu(QemuExitCode::Failed);
+    }
+
+    loop {}
+}